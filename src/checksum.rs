@@ -0,0 +1,78 @@
+use sha1::Sha1;
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+// Traits
+use std::io::Read;
+
+use AnimeFile;
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug)]
+pub enum Verification {
+    Intact,
+    Corrupt(String),
+    NoHash,
+    Unreadable(io::Error),
+}
+
+// Hash the contents of the file, returning the SHA-1 as a lowercase hex string.
+pub fn sha1_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let file = try!(File::open(path));
+    sha1_reader(file)
+}
+
+// Hash only the first `len` bytes of the file. Cheap way to tell apart files of the same size before
+// committing to hashing all of them.
+pub fn sha1_file_prefix<P: AsRef<Path>>(path: P, len: u64) -> io::Result<String> {
+    let file = try!(File::open(path));
+    sha1_reader(file.take(len))
+}
+
+fn sha1_reader<R: Read>(mut reader: R) -> io::Result<String> {
+    let mut hasher = Sha1::new();
+    let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+
+    loop {
+        let bytes_read = try!(reader.read(&mut buffer));
+        if bytes_read == 0 { break; }
+        hasher.update(&buffer[..bytes_read]);
+    }
+
+    Ok(hasher.digest().to_string())
+}
+
+// Compare the file contents against the hash embedded in the file name.
+pub fn verify(file: &AnimeFile) -> Verification {
+    let expected = match file.content_hash {
+        Some(ref h) => h,
+        None        => return Verification::NoHash,
+    };
+
+    info!("Verifying: {}", file.file_name.display());
+    match sha1_file(&file.file_name) {
+        Ok(ref actual) if actual == expected => Verification::Intact,
+        Ok(actual)                           => Verification::Corrupt(actual),
+        Err(e)                               => Verification::Unreadable(e),
+    }
+}
+
+#[test]
+fn sha1_file_hashes_contents() {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+
+    let path = env::temp_dir().join("anime-dupe-finder-sha1_file_hashes_contents");
+    {
+        let mut f = File::create(&path).unwrap();
+        f.write_all(b"abc").unwrap();
+    }
+    let hash = sha1_file(&path);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d".to_owned(), hash.unwrap());
+}
@@ -0,0 +1,124 @@
+// Subtitles, external audio, and other files that belong with an episode's video, so they can be
+// shown with it, and go wherever it goes.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Traits
+use std::os::unix::ffi::OsStrExt;
+
+#[cfg(test)] use anime_files;
+use AnimeFile;
+use GroupKey;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct Companion {
+    pub path:  PathBuf,
+    pub size:  u64,
+    pub mtime: u64,
+}
+
+impl Companion {
+    // Just the file name, for showing under the video it goes with.
+    pub fn name(&self) -> String {
+        match self.path.file_name() {
+            Some(n) => n.to_string_lossy().into_owned(),
+            None    => self.path.display().to_string(),
+        }
+    }
+}
+
+// Attach each companion to the video it goes with, all of them being from the same directory. A
+// companion goes with a video if its name is the video's name with a different extension, with
+// anything in between: "Show - S01E01 [...].en.ass" goes with "Show - S01E01 [...].mkv". If there's
+// no video like that, but the companion's name parses as an episode, it goes with the one video of
+// the same episode and release, if there is only one. Returns the companions that don't go with any
+// video.
+pub fn associate(videos: &mut Vec<AnimeFile>, companions: Vec<Companion>) -> Vec<Companion> {
+    let mut orphans = Vec::new();
+
+    for companion in companions {
+        let index = match by_stem(videos, &companion.path) {
+            Some(i) => Some(i),
+            None    => by_identity(videos, &companion.path),
+        };
+        match index {
+            Some(i) => {
+                debug!("{} goes with {}", companion.path.display(), videos[i].file_name.display());
+                videos[i].companions.push(companion);
+            },
+            None    => orphans.push(companion),
+        }
+    }
+
+    for video in videos.iter_mut() {
+        video.companions.sort();
+    }
+    orphans
+}
+
+// The video with the longest name that the companion's name starts with, followed by a '.'.
+fn by_stem(videos: &Vec<AnimeFile>, companion: &Path) -> Option<usize> {
+    let name = match companion.file_name() {
+        Some(n) => n.as_bytes(),
+        None    => return None,
+    };
+
+    let mut best: Option<(usize, usize)> = None;
+    for (i, video) in videos.iter().enumerate() {
+        let stem = match video.file_name.file_stem() {
+            Some(s) => s.as_bytes(),
+            None    => continue,
+        };
+        if name.len() > stem.len() && name.starts_with(stem) && name[stem.len()] == b'.' {
+            if best.map(|(_, len)| stem.len() > len).unwrap_or(true) {
+                best = Some((i, stem.len()));
+            }
+        }
+    }
+
+    best.map(|(i, _)| i)
+}
+
+fn by_identity(videos: &Vec<AnimeFile>, companion: &Path) -> Option<usize> {
+    let parsed = match AnimeFile::new(companion.to_path_buf()) {
+        Ok(Some(a)) => a,
+        _           => return None,
+    };
+
+    let mut matching: HashMap<GroupKey, Vec<usize>> = HashMap::new();
+    for (i, video) in videos.iter().enumerate() {
+        if video.release_group == parsed.release_group && video.version == parsed.version {
+            matching.entry(GroupKey::for_file(video)).or_insert(Vec::new()).push(i);
+        }
+    }
+
+    match matching.get(&GroupKey::for_file(&parsed)) {
+        Some(m) if m.len() == 1 => Some(m[0]),
+        _                       => None,
+    }
+}
+
+#[test]
+fn associate_matches_by_stem_then_identity() {
+    let companion = |name: &str| Companion { path: PathBuf::from(name), size: 0, mtime: 0 };
+    let mut videos = anime_files(vec!(
+        "./Fairy Tail - S01E01 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4",
+        "./Fairy Tail - S01E01 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls](8e28f917be6423ce5ee4deee1369eb4e2eb02e48).mkv",
+        "./Fairy Tail - S01E02 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls].mkv",
+    ));
+
+    let orphans = associate(&mut videos, vec!(
+        companion("./Fairy Tail - S01E01 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).en.ass"),
+        companion("./Fairy Tail - S01E01 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls].mka"),
+        companion("./Fairy Tail - S01E02 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls].idx"),
+        companion("./Fairy Tail - S01E02 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls].sub"),
+        companion("./Fairy Tail - S01E03 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls].srt"),
+    ));
+
+    assert_eq!(vec!("Fairy Tail - S01E01 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).en.ass"),
+               videos[0].companions.iter().map(|c| c.name()).collect::<Vec<String>>());
+    assert_eq!(vec!("Fairy Tail - S01E01 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls].mka"),
+               videos[1].companions.iter().map(|c| c.name()).collect::<Vec<String>>());
+    assert_eq!(2, videos[2].companions.len());
+    assert_eq!(vec!(companion("./Fairy Tail - S01E03 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls].srt")), orphans);
+}
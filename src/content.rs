@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hash;
+use std::path::Path;
+
+// Traits
+use std::os::unix::fs::MetadataExt;
+
+use checksum;
+use AnimeFile;
+
+// How much of each file to hash before deciding whether it's worth hashing the whole thing.
+const PARTIAL_HASH_SIZE: u64 = 1024 * 1024;
+
+// Find files with identical contents, regardless of name or location. Files are grouped by size,
+// then by a hash of their first few bytes, and only what is still a candidate after that, and bigger
+// than what was already hashed, gets a hash of its full contents.
+pub fn find_identical(files: Vec<AnimeFile>) -> Vec<Vec<AnimeFile>> {
+    let mut identical = Vec::new();
+
+    for same_size in split_groups(collapse_same_files(files), |f| {
+        if f.size == 0 {
+            debug!("Skipping empty file: {}", f.file_name.display());
+            None
+        } else {
+            Some(f.size)
+        }
+    }) {
+        for same_prefix in split_groups(same_size, |f| hash_or_skip(f, Some(PARTIAL_HASH_SIZE))) {
+            if same_prefix[0].size <= PARTIAL_HASH_SIZE {
+                identical.push(same_prefix);
+            } else {
+                let same_hash = split_groups(same_prefix, |f| hash_or_skip(f, None));
+                identical.extend(same_hash.into_iter());
+            }
+        }
+    }
+
+    for group in identical.iter_mut() {
+        group.sort();
+    }
+    identical.sort_by(|a, b| a[0].cmp(&b[0]));
+
+    identical
+}
+
+// Paths that lead to the same file, through hard links or symlinks, aren't copies of each other:
+// removing one doesn't free anything, and removing a symlink's target breaks it. Only one path to
+// each file is kept, a real file rather than a symlink if there is one, and otherwise whichever
+// sorts first.
+fn collapse_same_files(files: Vec<AnimeFile>) -> Vec<AnimeFile> {
+    let mut by_inode: HashMap<(u64, u64), AnimeFile> = HashMap::new();
+    let mut collapsed = Vec::new();
+
+    for file in files {
+        let key = match fs::metadata(&file.file_name) {
+            Ok(m)  => (m.dev(), m.ino()),
+            Err(_) => {
+                // Whatever is wrong with it will come up when it's hashed.
+                collapsed.push(file);
+                continue;
+            },
+        };
+        let replace = match by_inode.get(&key) {
+            Some(existing) => (is_symlink(&file.file_name), &file.file_name) < (is_symlink(&existing.file_name), &existing.file_name),
+            None           => true,
+        };
+        if replace {
+            if let Some(existing) = by_inode.insert(key, file) {
+                debug!("Same file as another path, skipping: {}", existing.file_name.display());
+            }
+        } else {
+            debug!("Same file as another path, skipping: {}", file.file_name.display());
+        }
+    }
+
+    collapsed.extend(by_inode.into_iter().map(|(_, f)| f));
+    collapsed
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).map(|m| m.file_type().is_symlink()).unwrap_or(false)
+}
+
+// Bucket the files by the key returned from `key_for`, and only keep the buckets that have more than
+// one file in them. Files without a key are dropped.
+fn split_groups<K, F>(files: Vec<AnimeFile>, mut key_for: F) -> Vec<Vec<AnimeFile>>
+    where K: Hash + Eq, F: FnMut(&AnimeFile) -> Option<K> {
+    let mut buckets: HashMap<K, Vec<AnimeFile>> = HashMap::new();
+
+    for file in files {
+        match key_for(&file) {
+            Some(key) => buckets.entry(key).or_insert(Vec::new()).push(file),
+            None      => { },
+        }
+    }
+
+    buckets.into_iter().map(|(_, v)| v).filter(|v| v.len() > 1).collect()
+}
+
+fn hash_or_skip(file: &AnimeFile, len: Option<u64>) -> Option<String> {
+    let result = match len {
+        Some(l) => checksum::sha1_file_prefix(&file.file_name, l),
+        None    => {
+            info!("Hashing: {}", file.file_name.display());
+            checksum::sha1_file(&file.file_name)
+        },
+    };
+
+    match result {
+        Ok(h)  => Some(h),
+        Err(e) => {
+            warn!("Unable to hash {}: {}", file.file_name.display(), e);
+            None
+        },
+    }
+}
+
+#[test]
+fn find_identical_skips_links_to_the_same_file() {
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use std::os::unix;
+    use std::path::PathBuf;
+
+    let root = env::temp_dir().join("anime-dupe-finder-find_identical_skips_links_to_the_same_file");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let name = |n: &str| root.join(format!("Fairy Tail - S01E0{} [HDTV][1280x720.H264AVC.AAC][Kyuubi].mkv", n));
+    File::create(name("1")).unwrap().write_all(b"episode").unwrap();
+    fs::hard_link(name("1"), name("2")).unwrap();
+    unix::fs::symlink(name("1"), name("3")).unwrap();
+    File::create(name("4")).unwrap().write_all(b"episode").unwrap();
+    unix::fs::symlink(name("4"), name("0")).unwrap();
+
+    let files = (0..5).map(|n| {
+        let mut file = AnimeFile::new(name(&n.to_string())).unwrap().unwrap();
+        file.size = 7;
+        file
+    }).collect();
+    let identical = find_identical(files);
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(1, identical.len());
+    assert_eq!(vec!(name("1"), name("4")), identical[0].iter().map(|f| f.file_name.clone()).collect::<Vec<PathBuf>>());
+}
+
+#[test]
+fn find_identical_includes_videos_that_dont_parse() {
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    let root = env::temp_dir().join("anime-dupe-finder-find_identical_includes_videos_that_dont_parse");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let parsed = root.join("Fairy Tail - S01E01 [HDTV][1280x720.H264AVC.AAC][Kyuubi].mkv");
+    let unparsed = root.join("fairy_tail_01.mkv");
+    File::create(&parsed).unwrap().write_all(b"episode").unwrap();
+    File::create(&unparsed).unwrap().write_all(b"episode").unwrap();
+
+    let mut files = vec!(AnimeFile::new(parsed.clone()).unwrap().unwrap(), AnimeFile::unparsed(unparsed.clone()));
+    for file in files.iter_mut() {
+        file.size = 7;
+    }
+    let identical = find_identical(files);
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(1, identical.len());
+    let mut names = identical[0].iter().map(|f| f.file_name.clone()).collect::<Vec<PathBuf>>();
+    names.sort();
+    assert_eq!(vec!(parsed, unparsed), names);
+}
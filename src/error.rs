@@ -0,0 +1,117 @@
+use ansi_term::Colour::Red;
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+// Traits
+use std::io::Write;
+
+// Something that went wrong with a single file or directory. None of these stop the run: they're
+// collected, and reported once everything else is done.
+#[derive(Debug)]
+pub enum Error {
+    // Couldn't list a directory, or get the metadata of a file.
+    Unreadable(PathBuf, io::Error),
+    // One of the directories to search isn't a directory.
+    NotADirectory(PathBuf),
+    // The file name looks like an episode, but has something in it that doesn't make sense.
+    Parse(PathBuf, String),
+    // Couldn't remove, move, or link a file.
+    Removal(PathBuf, io::Error),
+    // The file is different from when the plan was made.
+    Changed(PathBuf, String),
+    // Couldn't write to the journal.
+    Journal(PathBuf, io::Error),
+    // Couldn't read the user's selection.
+    Input(io::Error),
+    // Couldn't write an output file, like a plan.
+    Write(PathBuf, io::Error),
+    // The file chosen to keep doesn't match the hash in its name, or couldn't be read to check.
+    Unverified(PathBuf, String),
+}
+
+impl Error {
+    pub fn category(&self) -> &'static str {
+        match *self {
+            Error::Unreadable(_, _) => "Unreadable files and directories",
+            Error::NotADirectory(_) => "Not directories",
+            Error::Parse(_, _)      => "Unparseable file names",
+            Error::Removal(_, _)    => "Files that couldn't be removed, moved or linked",
+            Error::Changed(_, _)    => "Files that changed since the plan was made",
+            Error::Journal(_, _)    => "Journal write failures",
+            Error::Input(_)         => "Unreadable input",
+            Error::Write(_, _)      => "Files that couldn't be written",
+            Error::Unverified(_, _) => "Files to keep that failed verification",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Unreadable(ref p, ref e) => write!(f, "{}: {}", p.display(), e),
+            Error::NotADirectory(ref p)     => write!(f, "{}", p.display()),
+            Error::Parse(ref p, ref r)      => write!(f, "{}: {}", p.display(), r),
+            Error::Removal(ref p, ref e)    => write!(f, "{}: {}", p.display(), e),
+            Error::Changed(ref p, ref r)    => write!(f, "{}: {}", p.display(), r),
+            Error::Journal(ref p, ref e)    => write!(f, "{}: {}", p.display(), e),
+            Error::Input(ref e)             => write!(f, "{}", e),
+            Error::Write(ref p, ref e)      => write!(f, "{}: {}", p.display(), e),
+            Error::Unverified(ref p, ref r) => write!(f, "{}: {}", p.display(), r),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        self.category()
+    }
+}
+
+// Everything that went wrong during a run.
+pub struct Failures {
+    errors: Vec<Error>,
+}
+
+impl Failures {
+    pub fn new() -> Failures {
+        Failures { errors: Vec::new() }
+    }
+
+    pub fn record(&mut self, error: Error) {
+        warn!("{}", error);
+        self.errors.push(error);
+    }
+
+    pub fn extend(&mut self, errors: Vec<Error>) {
+        for error in errors {
+            self.record(error);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.len() == 0
+    }
+
+    // List everything that went wrong, grouped by what kind of problem it was.
+    pub fn print_summary(&self) {
+        if self.is_empty() { return; }
+
+        let mut by_category: BTreeMap<&'static str, Vec<&Error>> = BTreeMap::new();
+        for error in self.errors.iter() {
+            by_category.entry(error.category()).or_insert(Vec::new()).push(error);
+        }
+
+        let mut stderr = io::stderr();
+        let _ = writeln!(stderr, "{}", Red.bold().paint(format!("{} problem(s) found:", self.errors.len())));
+        for (category, errors) in by_category {
+            let _ = writeln!(stderr, "  {} ({}):", Red.paint(category), errors.len());
+            for error in errors {
+                let _ = writeln!(stderr, "    {}", error);
+            }
+        }
+    }
+}
@@ -0,0 +1,224 @@
+// Missing episodes: for each show and season, which episode numbers aren't anywhere in the
+// collection, going by the highest one that is, or by how many there should be, if that's known.
+use ansi_term::Colour::Yellow;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+// Traits
+use std::io::Read;
+use std::str::FromStr;
+
+use normalize_title;
+use report::season_label;
+#[cfg(test)] use anime_files;
+use AnimeFile;
+use EpisodeNum;
+use SeasonNum;
+
+// What was found of a single season of a show.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SeasonEpisodes {
+    // As it was first seen.
+    pub title:    String,
+    pub season:   SeasonNum,
+    pub episodes: BTreeSet<u16>,
+    pub specials: BTreeSet<u16>,
+    pub openings: BTreeSet<u16>,
+    pub closings: BTreeSet<u16>,
+}
+
+impl SeasonEpisodes {
+    fn new(title: &str, season: &SeasonNum) -> SeasonEpisodes {
+        SeasonEpisodes {
+            title:    title.to_owned(),
+            season:   season.clone(),
+            episodes: BTreeSet::new(),
+            specials: BTreeSet::new(),
+            openings: BTreeSet::new(),
+            closings: BTreeSet::new(),
+        }
+    }
+
+    // Regular episode numbers from 1 up to the highest seen, or the expected count if that's higher,
+    // that aren't in the collection.
+    pub fn missing(&self, expected: Option<u16>) -> Vec<u16> {
+        let highest = self.episodes.iter().next_back().map(|&e| e).unwrap_or(0);
+        let last = match expected {
+            Some(e) if e > highest => e,
+            _                      => highest,
+        };
+        // Counted in u32 so a season that runs to 65535 doesn't overflow.
+        (1..last as u32 + 1).map(|e| e as u16).filter(|e| !self.episodes.contains(e)).collect()
+    }
+}
+
+// Keyed by normalized title and season, so differences in case and punctuation don't split up a
+// show.
+pub fn collect(files: &Vec<AnimeFile>) -> BTreeMap<(String, SeasonNum), SeasonEpisodes> {
+    let mut seasons = BTreeMap::new();
+
+    for file in files.iter() {
+        let season = seasons.entry((normalize_title(&file.title), file.season.clone()))
+            .or_insert(SeasonEpisodes::new(&file.title, &file.season));
+        match file.episode {
+            EpisodeNum::Episode(n) => { season.episodes.insert(n); },
+            EpisodeNum::Special(n) => { season.specials.insert(n); },
+            EpisodeNum::Opening(n) => { season.openings.insert(n); },
+            EpisodeNum::Closing(n) => { season.closings.insert(n); },
+            _                      => { },
+        }
+    }
+
+    seasons
+}
+
+// How many episodes each season should have, from a file with a line per season of: the title, the
+// season number (empty for shows without seasons), and the number of episodes, separated by tabs.
+// Blank lines and lines starting with '#' are ignored.
+pub fn read_expected_counts(path: &Path) -> Result<BTreeMap<(String, SeasonNum), u16>, String> {
+    let mut contents = String::new();
+    match File::open(path).and_then(|mut f| f.read_to_string(&mut contents)) {
+        Ok(_)  => { },
+        Err(e) => return Err(format!("Unable to read {}: {}", path.display(), e)),
+    }
+
+    let mut counts = BTreeMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim() == "" || line.starts_with("#") {
+            continue;
+        }
+        match parse_expected_count(line) {
+            Ok((title, season, count)) => { counts.insert((normalize_title(&title), season), count); },
+            Err(e)                     => return Err(format!("{} line {}: {}", path.display(), i + 1, e)),
+        }
+    }
+
+    Ok(counts)
+}
+
+fn parse_expected_count(line: &str) -> Result<(String, SeasonNum, u16), String> {
+    let fields: Vec<&str> = line.split('\t').map(|f| f.trim()).collect();
+    if fields.len() != 3 {
+        return Err(format!("Expected a title, season, and episode count separated by tabs, not \"{}\"", line));
+    }
+
+    let season = match fields[1] {
+        "" => SeasonNum::NoSeason,
+        s  => match u8::from_str(s) {
+            Ok(s)  => SeasonNum::Season(s),
+            Err(e) => return Err(format!("Invalid season \"{}\": {}", s, e)),
+        },
+    };
+    let count = match u16::from_str(fields[2]) {
+        Ok(c)  => c,
+        Err(e) => return Err(format!("Invalid episode count \"{}\": {}", fields[2], e)),
+    };
+
+    Ok((fields[0].to_owned(), season, count))
+}
+
+// "1, 3-5, 8".
+pub fn format_numbers<'a, I: IntoIterator<Item=&'a u16>>(numbers: I) -> String {
+    let mut ranges: Vec<(u16, u16)> = Vec::new();
+    for &n in numbers {
+        if let Some(last) = ranges.last_mut() {
+            if last.1 as u32 + 1 == n as u32 {
+                last.1 = n;
+                continue;
+            }
+        }
+        ranges.push((n, n));
+    }
+
+    ranges.iter().map(|&(start, end)| {
+        if start == end { format!("{}", start) } else { format!("{}-{}", start, end) }
+    }).collect::<Vec<String>>().join(", ")
+}
+
+pub fn print_gaps<W: io::Write>(out: &mut W, seasons: &BTreeMap<(String, SeasonNum), SeasonEpisodes>, expected: &BTreeMap<(String, SeasonNum), u16>) -> io::Result<()> {
+    for (key, season) in seasons.iter() {
+        let expected_count = expected.get(key).map(|&c| c);
+        let missing = season.missing(expected_count);
+
+        let heading = match season.season {
+            SeasonNum::NoSeason => season.title.clone(),
+            _                   => format!("{} {}", season.title, season_label(&season.season)),
+        };
+        try!(writeln!(out, "{}", Yellow.paint(format!("{}:", heading))));
+        if season.episodes.len() > 0 || expected_count.is_some() {
+            let of = match expected_count {
+                Some(c) => format!("{} expected", c),
+                None    => format!("up to {}", season.episodes.iter().next_back().map(|&e| e).unwrap_or(0)),
+            };
+            if missing.len() == 0 {
+                try!(writeln!(out, "    No missing episodes ({})", of));
+            } else {
+                try!(writeln!(out, "    Missing episodes ({}): {}", of, format_numbers(missing.iter())));
+            }
+            if let Some(c) = expected_count {
+                let extra: Vec<u16> = season.episodes.iter().filter(|&&e| e > c).map(|&e| e).collect();
+                if extra.len() > 0 {
+                    try!(writeln!(out, "    More episodes than expected: {}", format_numbers(extra.iter())));
+                }
+            }
+        }
+        if season.specials.len() > 0 {
+            try!(writeln!(out, "    Specials: {}", format_numbers(season.specials.iter())));
+        }
+        if season.openings.len() > 0 {
+            try!(writeln!(out, "    Openings: {}", format_numbers(season.openings.iter())));
+        }
+        if season.closings.len() > 0 {
+            try!(writeln!(out, "    Closings: {}", format_numbers(season.closings.iter())));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn collect_finds_missing_episodes() {
+    let files = anime_files(vec!(
+        "./Fairy Tail - S01E01 [HDTV][1280x720.H264AVC.AAC][Kyuubi].mp4",
+        "./fairy tail - S01E02 [HDTV][1280x720.H264AVC.AAC][Kyuubi].mp4",
+        "./Fairy Tail - S01E06 [HDTV][1280x720.H264AVC.AAC][Kyuubi].mp4",
+        "./Fairy Tail - S01ES01 [HDTV][1280x720.H264AVC.AAC][Kyuubi].mp4",
+        "./Fairy Tail - S01EO02 [HDTV][1280x720.H264AVC.AAC][Kyuubi].mp4",
+        "./Fairy Tail - S02E02 [HDTV][1280x720.H264AVC.AAC][Kyuubi].mp4",
+    ));
+
+    let seasons = collect(&files);
+    let first = &seasons[&("fairy tail".to_owned(), SeasonNum::Season(1))];
+
+    assert_eq!(2, seasons.len());
+    assert_eq!(vec!(3, 4, 5), first.missing(None));
+    assert_eq!(vec!(3, 4, 5, 7, 8), first.missing(Some(8)));
+    assert_eq!(vec!(1), seasons[&("fairy tail".to_owned(), SeasonNum::Season(2))].missing(None));
+    assert_eq!("1",  format_numbers(first.specials.iter()));
+    assert_eq!("2",  format_numbers(first.openings.iter()));
+    assert_eq!("3-5, 7-8, 10", format_numbers(vec!(3, 4, 5, 7, 8, 10).iter()));
+}
+
+#[test]
+fn missing_and_format_numbers_handle_the_highest_episode_number() {
+    let mut season = SeasonEpisodes::new("Fairy Tail", &SeasonNum::Season(1));
+    season.episodes.insert(65535);
+
+    let missing = season.missing(Some(65535));
+    assert_eq!(65534, missing.len());
+    assert_eq!(Some(&65534), missing.last());
+    assert_eq!("1-65534", format_numbers(missing.iter()));
+    assert_eq!("65534-65535", format_numbers(vec!(65534, 65535).iter()));
+    assert_eq!("65535, 0", format_numbers(vec!(65535, 0).iter()));
+}
+
+#[test]
+fn parse_expected_count_reads_tab_separated_fields() {
+    assert_eq!(Ok(("Fairy Tail".to_owned(), SeasonNum::Season(1), 48)), parse_expected_count("Fairy Tail\t1\t48"));
+    assert_eq!(Ok(("Akira".to_owned(), SeasonNum::NoSeason, 1)), parse_expected_count("Akira\t\t1"));
+    assert!(parse_expected_count("Fairy Tail\t1").is_err());
+    assert!(parse_expected_count("Fairy Tail\t1\tmany").is_err());
+}
@@ -0,0 +1,268 @@
+use ansi_term::Colour::{Green, Red, Yellow};
+use libc;
+use rustc_serialize::json::{Json, ToJson};
+use time;
+
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Traits
+use std::io::{BufRead, Write};
+
+use checksum;
+use link;
+use plan::{path_from_json, path_to_json};
+use removal;
+
+// One file that was removed (or restored), as recorded in the journal.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct JournalEntry {
+    pub id:          String,
+    pub session:     String,
+    pub timestamp:   String,
+    // "trash", "move", "delete", "link", or "restore".
+    pub action:      String,
+    pub original:    PathBuf,
+    pub destination: Option<PathBuf>,
+    pub size:        u64,
+    pub hash:        Option<String>,
+    // For "restore" entries, the id of the entry that was undone.
+    pub undoes:      Option<String>,
+}
+
+impl ToJson for JournalEntry {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("id".to_owned(),        self.id.to_json());
+        obj.insert("session".to_owned(),   self.session.to_json());
+        obj.insert("timestamp".to_owned(), self.timestamp.to_json());
+        obj.insert("action".to_owned(),    self.action.to_json());
+        obj.insert("original".to_owned(),  path_to_json(&self.original));
+        obj.insert("size".to_owned(),      self.size.to_json());
+        if let Some(ref d) = self.destination {
+            obj.insert("destination".to_owned(), path_to_json(d));
+        }
+        if let Some(ref h) = self.hash {
+            obj.insert("hash".to_owned(), h.to_json());
+        }
+        if let Some(ref u) = self.undoes {
+            obj.insert("undoes".to_owned(), u.to_json());
+        }
+        Json::Object(obj)
+    }
+}
+
+impl JournalEntry {
+    fn from_json(json: &Json) -> Result<JournalEntry, String> {
+        let optional_string = |name: &str| json.find(name).and_then(|j| j.as_string()).map(|s| s.to_owned());
+        let string_field = |name: &str| -> Result<String, String> {
+            match optional_string(name) {
+                Some(s) => Ok(s),
+                None    => Err(format!("Missing or invalid \"{}\" in journal entry: {}", name, json)),
+            }
+        };
+
+        Ok(JournalEntry {
+            id:          try!(string_field("id")),
+            session:     try!(string_field("session")),
+            timestamp:   try!(string_field("timestamp")),
+            action:      try!(string_field("action")),
+            original:    match json.find("original").and_then(|j| path_from_json(j)) {
+                Some(p) => p,
+                None    => return Err(format!("Missing or invalid \"original\" in journal entry: {}", json)),
+            },
+            destination: json.find("destination").and_then(|j| path_from_json(j)),
+            size:        json.find("size").and_then(|j| j.as_u64()).unwrap_or(0),
+            hash:        optional_string("hash"),
+            undoes:      optional_string("undoes"),
+        })
+    }
+}
+
+// Append-only record of every file this tool has removed, so that the removals can be undone.
+pub struct Journal {
+    path:        PathBuf,
+    session:     String,
+    next_number: u64,
+}
+
+impl Journal {
+    pub fn new(path: PathBuf) -> Journal {
+        let session = format!("{}-{}", time::now().strftime("%Y%m%dT%H%M%S").map(|t| t.to_string()).unwrap_or("session".to_owned()),
+                              unsafe { libc::getpid() });
+        Journal { path: path, session: session, next_number: 1 }
+    }
+
+    pub fn default_path() -> PathBuf {
+        let data_home = match env::var_os("XDG_DATA_HOME") {
+            Some(ref d) if d.len() > 0 => PathBuf::from(d),
+            _                          => env::home_dir().unwrap_or(PathBuf::from(".")).join(".local").join("share"),
+        };
+
+        data_home.join("anime-dupe-finder").join("journal")
+    }
+
+    // The size and hash to record for a file. This needs to happen before the file is removed.
+    pub fn describe_file(path: &Path) -> (u64, Option<String>) {
+        let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        let hash = match checksum::sha1_file(path) {
+            Ok(h)  => Some(h),
+            Err(e) => {
+                warn!("Unable to hash {} for the journal: {}", path.display(), e);
+                None
+            },
+        };
+        (size, hash)
+    }
+
+    pub fn record(&mut self, action: &str, original: &Path, destination: Option<&Path>, size: u64, hash: Option<String>, undoes: Option<String>) -> io::Result<()> {
+        let entry = JournalEntry {
+            id:          format!("{}:{}", self.session, self.next_number),
+            session:     self.session.clone(),
+            timestamp:   time::now().rfc3339().to_string(),
+            action:      action.to_owned(),
+            original:    original.to_path_buf(),
+            destination: destination.map(|d| d.to_path_buf()),
+            size:        size,
+            hash:        hash,
+            undoes:      undoes,
+        };
+        self.next_number += 1;
+
+        if let Some(parent) = self.path.parent() {
+            try!(fs::create_dir_all(parent));
+        }
+        let mut file = try!(OpenOptions::new().append(true).create(true).open(&self.path));
+        writeln!(file, "{}", entry.to_json())
+    }
+
+    pub fn read_entries(&self) -> Result<Vec<JournalEntry>, String> {
+        let file = match File::open(&self.path) {
+            Ok(f)  => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(format!("Unable to read {}: {}", self.path.display(), e)),
+        };
+
+        let mut entries = Vec::new();
+        for line in io::BufReader::new(file).lines() {
+            let line = match line {
+                Ok(l)  => l,
+                Err(e) => return Err(format!("Unable to read {}: {}", self.path.display(), e)),
+            };
+            if line.trim() == "" { continue; }
+            match Json::from_str(&line) {
+                Ok(j)  => entries.push(try!(JournalEntry::from_json(&j))),
+                Err(e) => return Err(format!("Unable to parse journal line \"{}\": {}", line, e)),
+            }
+        }
+
+        Ok(entries)
+    }
+
+    // Put back the removals from the given session (the most recent one if None), or the last N
+    // removals, newest first. Returns how many couldn't be undone.
+    pub fn undo(&mut self, session: Option<&str>, last: Option<usize>) -> Result<usize, String> {
+        let entries = try!(self.read_entries());
+        let undone: HashSet<String> = entries.iter().filter_map(|e| e.undoes.clone()).collect();
+        let mut candidates: Vec<JournalEntry> = entries.into_iter()
+            .filter(|e| e.action != "restore" && !undone.contains(&e.id))
+            .collect();
+
+        if let Some(n) = last {
+            let skip = if candidates.len() > n { candidates.len() - n } else { 0 };
+            candidates = candidates.split_off(skip);
+        } else {
+            let wanted = match session {
+                Some(s) => s.to_owned(),
+                None    => match candidates.last() {
+                    Some(e) => e.session.clone(),
+                    None    => return Ok(0),
+                },
+            };
+            candidates.retain(|e| e.session == wanted);
+        }
+
+        let mut failures = 0;
+        for entry in candidates.iter().rev() {
+            match self.restore(entry) {
+                Ok(_)  => println!("  {} {}", Green.bold().paint("Restored:"), entry.original.display()),
+                Err(e) => {
+                    println!("  {} {}: {}", Red.bold().paint("Unable to restore"), entry.original.display(), e);
+                    failures += 1;
+                },
+            }
+        }
+
+        Ok(failures)
+    }
+
+    fn restore(&mut self, entry: &JournalEntry) -> Result<(), String> {
+        let destination = match entry.destination {
+            Some(ref d) => d.clone(),
+            None        => return Err("it was deleted outright".to_owned()),
+        };
+
+        if let Some(ref expected) = entry.hash {
+            match checksum::sha1_file(&destination) {
+                Ok(ref actual) if actual == expected => { },
+                Ok(actual)                           => return Err(format!("{} hashes to {}, not {}", destination.display(), actual, expected)),
+                Err(e)                               => return Err(format!("{}: {}", destination.display(), e)),
+            }
+        } else {
+            println!("  {} {}", Yellow.paint("No hash recorded for"), entry.original.display());
+        }
+
+        let restored = if entry.action == "link" {
+            link::replace_link_with_copy(&destination, &entry.original)
+        } else {
+            removal::move_file(&destination, &entry.original)
+        };
+        if let Err(e) = restored {
+            return Err(format!("{}", e));
+        }
+        if entry.action == "trash" {
+            remove_trash_info(&destination);
+        }
+
+        let undoes = Some(entry.id.clone());
+        match self.record("restore", &entry.original, Some(&destination), entry.size, entry.hash.clone(), undoes) {
+            Ok(_)  => Ok(()),
+            Err(e) => Err(format!("Restored, but unable to update the journal: {}", e)),
+        }
+    }
+}
+
+// Files in the trash live in Trash/files/NAME, with their details in Trash/info/NAME.trashinfo.
+fn remove_trash_info(trashed: &Path) {
+    let trash_dir = trashed.parent().and_then(|p| p.parent());
+    if let (Some(dir), Some(name)) = (trash_dir, trashed.file_name()) {
+        let mut info_name = name.to_os_string();
+        info_name.push(".trashinfo");
+        let info = dir.join("info").join(info_name);
+        if let Err(e) = fs::remove_file(&info) {
+            warn!("Unable to remove {}: {}", info.display(), e);
+        }
+    }
+}
+
+#[test]
+fn journal_entry_round_trips_through_json() {
+    let entry = JournalEntry {
+        id:          "20161017T101500-42:1".to_owned(),
+        session:     "20161017T101500-42".to_owned(),
+        timestamp:   "2016-10-17T10:15:00-07:00".to_owned(),
+        action:      "move".to_owned(),
+        original:    PathBuf::from("/anime/Fairy Tail/Fairy Tail - S01E01.mkv"),
+        destination: Some(PathBuf::from("/quarantine/Fairy Tail/Fairy Tail - S01E01.mkv")),
+        size:        1234,
+        hash:        Some("304a75ced2d46016e3df0c8b4607f4afe4e75952".to_owned()),
+        undoes:      None,
+    };
+
+    let json = Json::from_str(&entry.to_json().to_string()).unwrap();
+    assert_eq!(entry, JournalEntry::from_json(&json).unwrap());
+}
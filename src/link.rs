@@ -0,0 +1,157 @@
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::os::unix;
+use std::path::{Path, PathBuf};
+
+// Traits
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LinkKind {
+    Hard,
+    Symbolic,
+}
+
+impl LinkKind {
+    pub fn names() -> &'static [&'static str] {
+        &["hard", "sym"]
+    }
+
+    pub fn from_name(name: &str) -> Result<LinkKind, String> {
+        match name {
+            "hard" => Ok(LinkKind::Hard),
+            "sym"  => Ok(LinkKind::Symbolic),
+            _      => Err(format!("Unknown link type: {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LinkKind::Hard     => "hard",
+            LinkKind::Symbolic => "sym",
+        }
+    }
+}
+
+// Whether the two paths already lead to the same file (hard links to the same inode, or a symlink
+// to the other).
+pub fn is_same_file(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(ma), Ok(mb)) => ma.dev() == mb.dev() && ma.ino() == mb.ino(),
+        _                => false,
+    }
+}
+
+// Replace the duplicate with a link to the keeper. The contents are compared first, and the link is
+// created under a temporary name next to the duplicate and renamed over it, so the duplicate's path
+// never stops existing.
+pub fn replace_with_link(keeper: &Path, duplicate: &Path, kind: LinkKind) -> io::Result<()> {
+    if !try!(contents_identical(keeper, duplicate)) {
+        return Err(io::Error::new(io::ErrorKind::Other, format!("{} and {} are not identical", keeper.display(), duplicate.display())));
+    }
+
+    let temp_path = try!(temp_path_for(duplicate));
+    let created = match kind {
+        LinkKind::Hard     => fs::hard_link(keeper, &temp_path),
+        LinkKind::Symbolic => keeper.canonicalize().and_then(|target| unix::fs::symlink(target, &temp_path)),
+    };
+    try!(created);
+
+    match fs::rename(&temp_path, duplicate) {
+        Ok(_)  => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e)
+        },
+    }
+}
+
+// Undo replace_with_link: put a real copy of the keeper back in place of the link.
+pub fn replace_link_with_copy(keeper: &Path, link: &Path) -> io::Result<()> {
+    let temp_path = try!(temp_path_for(link));
+    try!(fs::copy(keeper, &temp_path));
+
+    match fs::rename(&temp_path, link) {
+        Ok(_)  => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e)
+        },
+    }
+}
+
+fn temp_path_for(path: &Path) -> io::Result<PathBuf> {
+    let mut temp_name = OsString::from(".");
+    match path.file_name() {
+        Some(n) => temp_name.push(n),
+        None    => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("No file name in {}", path.display()))),
+    };
+    temp_name.push(".anime-dupe-finder-tmp");
+    let temp_path = path.with_file_name(temp_name);
+    if fs::symlink_metadata(&temp_path).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", temp_path.display())));
+    }
+
+    Ok(temp_path)
+}
+
+fn contents_identical(a: &Path, b: &Path) -> io::Result<bool> {
+    let (mut file_a, mut file_b) = (try!(File::open(a)), try!(File::open(b)));
+    if try!(file_a.metadata()).len() != try!(file_b.metadata()).len() {
+        return Ok(false);
+    }
+
+    let mut buffer_a = vec![0u8; READ_BUFFER_SIZE];
+    let mut buffer_b = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        let read_a = try!(read_full(&mut file_a, &mut buffer_a));
+        let read_b = try!(read_full(&mut file_b, &mut buffer_b));
+        if read_a != read_b || buffer_a[..read_a] != buffer_b[..read_b] {
+            return Ok(false);
+        }
+        if read_a == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+// Fill the buffer unless the end of the file gets in the way.
+fn read_full(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while total < buffer.len() {
+        let read = try!(file.read(&mut buffer[total..]));
+        if read == 0 { break; }
+        total += read;
+    }
+    Ok(total)
+}
+
+#[test]
+fn replace_with_link_links_identical_files() {
+    use std::env;
+    use std::io::Write;
+
+    let root = env::temp_dir().join("anime-dupe-finder-replace_with_link_links_identical_files");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let (keeper, duplicate, different) = (root.join("keeper.mkv"), root.join("duplicate.mkv"), root.join("different.mkv"));
+    File::create(&keeper).unwrap().write_all(b"episode").unwrap();
+    File::create(&duplicate).unwrap().write_all(b"episode").unwrap();
+    File::create(&different).unwrap().write_all(b"epilogue").unwrap();
+
+    let linked = replace_with_link(&keeper, &duplicate, LinkKind::Hard);
+    let refused = replace_with_link(&keeper, &different, LinkKind::Hard);
+    let same_after_link = is_same_file(&keeper, &duplicate);
+    let same_after_refusal = is_same_file(&keeper, &different);
+    fs::remove_dir_all(&root).unwrap();
+
+    assert!(linked.is_ok());
+    assert!(refused.is_err());
+    assert!(same_after_link);
+    assert!(!same_after_refusal);
+}
@@ -6,10 +6,9 @@ extern crate regex;
 
 #[macro_use]
 extern crate clap;
-#[cfg(not(test))] use clap::{Arg, App};
+#[cfg(not(test))] use clap::{Arg, ArgMatches, App, AppSettings, OsValues, SubCommand};
 
 extern crate glob;
-use glob::Pattern;
 
 #[macro_use]
 extern crate log;
@@ -18,18 +17,27 @@ extern crate env_logger;
 extern crate ansi_term;
 use ansi_term::Colour::{Green, Red, Yellow, Blue};
 
+extern crate libc;
+extern crate rustc_serialize;
+extern crate sha1;
+extern crate time;
+
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs;
 use std::io;
 use std::mem;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::mpsc::Sender;
 use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::UNIX_EPOCH;
 
 // Traits
-use std::error::Error;
+use std::error::Error as StdError;
 use std::io::Write;
 
 extern crate collections;
@@ -38,13 +46,41 @@ use collections::borrow::ToOwned;
 extern crate core;
 use core::str::FromStr;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+mod checksum;
+use checksum::Verification;
+mod companion;
+use companion::Companion;
+mod content;
+mod error;
+use error::{Error, Failures};
+mod gaps;
+mod journal;
+mod link;
+use link::LinkKind;
+use journal::Journal;
+mod output;
+use output::{Format, RecordWriter};
+mod plan;
+use plan::{Plan, PlanEntry};
+mod policy;
+use policy::KeepPolicy;
+mod ranking;
+mod removal;
+use removal::{Quarantine, RemovalMethod};
+mod report;
+mod stats;
+use stats::Stats;
+mod trash;
+mod walk;
+use walk::{FileKind, PathPattern, SymlinkPolicy, Visited, WalkOptions};
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 enum SeasonNum {
     Season(u8),
     NoSeason,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
 enum EpisodeNum {
     Episode(u16),
     Opening(u16),
@@ -70,9 +106,110 @@ enum SourceMedia {
     UnknownMedia,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum VideoCodec {
+    H264,
+    HEVC,
+    XviD,
+    AV1,
+    Other(String),
+}
+
+impl VideoCodec {
+    pub fn from_tag(tag: &str) -> VideoCodec {
+        VideoCodec::from_known_tag(tag).unwrap_or_else(|| {
+            warn!("Found unmatched video codec: {}", tag);
+            VideoCodec::Other(tag.to_owned())
+        })
+    }
+
+    // None if the tag isn't a video codec we know.
+    pub fn from_known_tag(tag: &str) -> Option<VideoCodec> {
+        let normalized = normalize_codec_tag(tag);
+        if normalized.contains("HEVC") || normalized.contains("H265") || normalized.contains("X265") {
+            Some(VideoCodec::HEVC)
+        } else if normalized.contains("H264") || normalized.contains("AVC") || normalized.contains("X264") {
+            Some(VideoCodec::H264)
+        } else if normalized.contains("XVID") {
+            Some(VideoCodec::XviD)
+        } else if normalized.contains("AV1") {
+            Some(VideoCodec::AV1)
+        } else {
+            None
+        }
+    }
+
+    // The codec's name, or the tag as it appeared in the file name if it isn't one we know.
+    pub fn name(&self) -> String {
+        match *self {
+            VideoCodec::Other(ref tag) => tag.clone(),
+            _                          => format!("{:?}", self),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum AudioCodec {
+    FLAC,
+    AAC,
+    AC3,
+    Vorbis,
+    Opus,
+    Other(String),
+}
+
+impl AudioCodec {
+    pub fn from_tag(tag: &str) -> AudioCodec {
+        AudioCodec::from_known_tag(tag).unwrap_or_else(|| {
+            warn!("Found unmatched audio codec: {}", tag);
+            AudioCodec::Other(tag.to_owned())
+        })
+    }
+
+    // None if the tag isn't an audio codec we know.
+    pub fn from_known_tag(tag: &str) -> Option<AudioCodec> {
+        let normalized = normalize_codec_tag(tag);
+        if normalized.starts_with("FLAC") {
+            Some(AudioCodec::FLAC)
+        } else if normalized.starts_with("AAC") {
+            Some(AudioCodec::AAC)
+        } else if normalized.starts_with("AC3") {
+            Some(AudioCodec::AC3)
+        } else if normalized.starts_with("VORBIS") {
+            Some(AudioCodec::Vorbis)
+        } else if normalized.starts_with("OPUS") {
+            Some(AudioCodec::Opus)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            AudioCodec::Other(ref tag) => tag.clone(),
+            _                          => format!("{:?}", self),
+        }
+    }
+}
+
+// Codec tags show up as "H264AVC", "Vorbis Ogg Vorbis_", "x265", etc. Compare them uppercased with
+// everything but letters and digits stripped out.
+fn normalize_codec_tag(tag: &str) -> String {
+    tag.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_uppercase()
+}
+
+// Returns the bit depth if the tag is a bit depth marker ("Hi10P", "10bit", "8-bit", ...).
+fn bit_depth_from_tag(tag: &str) -> Option<u8> {
+    match &normalize_codec_tag(tag)[..] {
+        "HI10P" | "HI10" | "10BIT" => Some(10),
+        "8BIT"                     => Some(8),
+        _                          => None,
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 struct AnimeFile {
-    pub file_name:         String,
+    pub file_name:         PathBuf,
     pub title:             String,
     pub season:            SeasonNum,
     pub episode:           EpisodeNum,
@@ -80,15 +217,29 @@ struct AnimeFile {
     pub resolution_width:  Option<u64>,
     pub resolution_height: Option<u64>,
     pub version:           u8,
+    pub release_group:     Option<String>,
+    pub video_codec:       Option<VideoCodec>,
+    pub audio_codec:       Option<AudioCodec>,
+    pub bit_depth:         Option<u8>,
+    pub content_hash:      Option<String>,
+    pub size:              u64,
+    pub mtime:             u64,
+    // Subtitles and such that go with this file.
+    pub companions:        Vec<Companion>,
 }
 
 impl AnimeFile {
-    pub fn new(file: String) -> Option<AnimeFile> {
+    // Ok(None) if the file name doesn't look like an episode at all, and an error if it does, but
+    // something in it can't be made sense of. Names that aren't valid UTF-8 are parsed as if the
+    // invalid parts were replacement characters; file_name is always the real path.
+    pub fn new(path: PathBuf) -> Result<Option<AnimeFile>, Error> {
+        let file = path.to_string_lossy().into_owned();
         // (?:Ep|S\d+x?E)((?:C|S|T)?)(\d+)
-        let re = regex!(r"^.*/(?P<title>.*) - (?:Ep|S(?P<season>\d+)x?E)(?P<type>(?:C|S|T|O)?)(?P<episode>\d+)(?:v(?P<version>\d+))?(?: \[(?P<media>.+?)\]\[(?P<width>\d+)x(?P<height>\d+))?");
+        let re = regex!(r"^.*/(?P<title>.*) - (?:Ep|S(?P<season>\d+)x?E)(?P<type>(?:C|S|T|O)?)(?P<episode>\d+)(?:v(?P<version>\d+))?(?: \[(?P<media>.+?)\]\[(?P<width>\d+)x(?P<height>\d+)(?P<codecs>[^\]]*)\](?:\[(?P<group>[^\]]+)\])?)?");
+        let hash_re = regex!(r"\((?P<hash>[0-9A-Fa-f]{40})\)[^/]*$");
         let captures = match re.captures(&file) {
             Some(c) => { c },
-            None    => { return None; },
+            None    => { return Ok(None); },
         };
         debug!("Full match: |{}|", captures.at(0).unwrap_or(""));
         debug!("Matched title:   |{}|", captures.name("title").unwrap_or(""));
@@ -99,11 +250,16 @@ impl AnimeFile {
         debug!("Matched width:   |{}|", captures.name("width").unwrap_or(""));
         debug!("Matched height:  |{}|", captures.name("height").unwrap_or(""));
         debug!("Matched version: |{}|", captures.name("version").unwrap_or(""));
+        debug!("Matched codecs:  |{}|", captures.name("codecs").unwrap_or(""));
+        debug!("Matched group:   |{}|", captures.name("group").unwrap_or(""));
 
         let title = captures.name("title").unwrap_or("").to_owned();
-        let season:  SeasonNum  = if captures.name("season").unwrap_or("")  == "" { SeasonNum::NoSeason  } else { SeasonNum::Season(u8::from_str(captures.name("season").unwrap()).unwrap()) };
+        let season:  SeasonNum  = match captures.name("season").unwrap_or("") {
+            "" => SeasonNum::NoSeason,
+            s  => SeasonNum::Season(try!(parse_number(&path, "season", s))),
+        };
         let episode: EpisodeNum = if captures.name("episode").unwrap_or("") == "" { EpisodeNum::NoEpisode } else {
-            let ep_num: u16 = u16::from_str(captures.name("episode").unwrap_or("")).unwrap();
+            let ep_num: u16 = try!(parse_number(&path, "episode", captures.name("episode").unwrap_or("")));
             match captures.name("type").unwrap_or("") {
                 "C" => { EpisodeNum::Closing(ep_num) },
                 "S" => { EpisodeNum::Special(ep_num) },
@@ -134,11 +290,11 @@ impl AnimeFile {
         };
         let width: Option<u64> = match captures.name("width").unwrap_or("") {
             "" => None,
-            _  => Some(u64::from_str(captures.name("width").unwrap_or("")).unwrap()),
+            w  => Some(try!(parse_number(&path, "width", w))),
         };
         let height: Option<u64> = match captures.name("height").unwrap_or("") {
             "" => None,
-            _  => Some(u64::from_str(captures.name("height").unwrap_or("")).unwrap()),
+            h  => Some(try!(parse_number(&path, "height", h))),
         };
         let version: u8 = match u8::from_str(captures.name("version").unwrap_or("1")) {
             Err(e) => {
@@ -147,9 +303,47 @@ impl AnimeFile {
             },
             Ok(v)  => v,
         };
+        let release_group: Option<String> = match captures.name("group").unwrap_or("") {
+            "" => None,
+            g  => Some(g.to_owned()),
+        };
+
+        // The codecs follow the resolution, separated by '.': "1920x1080.H264AVC.Hi10P.FLAC". Tags
+        // are sorted into bit depth, video, and audio by what they are, since any of them can be left
+        // out. Tags that aren't any codec we know fill whichever of video and audio is left, in order.
+        let mut video_codec: Option<VideoCodec> = None;
+        let mut audio_codec: Option<AudioCodec> = None;
+        let mut bit_depth:   Option<u8>         = None;
+        let mut unknown_tags = Vec::new();
+        for tag in captures.name("codecs").unwrap_or("").split('.').filter(|t| t.trim() != "") {
+            if let Some(depth) = bit_depth_from_tag(tag) {
+                bit_depth = Some(depth);
+            } else if let Some(codec) = VideoCodec::from_known_tag(tag) {
+                video_codec = video_codec.or(Some(codec));
+            } else if let Some(codec) = AudioCodec::from_known_tag(tag) {
+                audio_codec = audio_codec.or(Some(codec));
+            } else {
+                unknown_tags.push(tag);
+            }
+        }
+        for tag in unknown_tags {
+            if video_codec.is_none() {
+                video_codec = Some(VideoCodec::from_tag(tag));
+            } else if audio_codec.is_none() {
+                audio_codec = Some(AudioCodec::from_tag(tag));
+            } else {
+                debug!("Ignoring extra codec tag: {}", tag);
+            }
+        }
+
+        let content_hash: Option<String> = match hash_re.captures(&file) {
+            Some(c) => c.name("hash").map(|h| h.to_lowercase()),
+            None    => None,
+        };
+        debug!("Matched hash:    |{}|", content_hash.clone().unwrap_or("".to_owned()));
 
         let af = AnimeFile {
-            file_name:         file.clone(),
+            file_name:         path.clone(),
             title:             title,
             season:            season,
             episode:           episode,
@@ -157,12 +351,100 @@ impl AnimeFile {
             resolution_width:  width,
             resolution_height: height,
             version:           version,
+            release_group:     release_group,
+            video_codec:       video_codec,
+            audio_codec:       audio_codec,
+            bit_depth:         bit_depth,
+            content_hash:      content_hash,
+            size:              0,
+            mtime:             0,
+            companions:        Vec::new(),
         };
 
-        Some(af)
+        Ok(Some(af))
+    }
+
+    // A video whose name doesn't look like an episode, which can still be compared by its contents.
+    pub fn unparsed(path: PathBuf) -> AnimeFile {
+        AnimeFile {
+            title:             path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or(String::new()),
+            file_name:         path,
+            season:            SeasonNum::NoSeason,
+            episode:           EpisodeNum::NoEpisode,
+            source_media:      SourceMedia::UnknownMedia,
+            resolution_width:  None,
+            resolution_height: None,
+            version:           1,
+            release_group:     None,
+            video_codec:       None,
+            audio_codec:       None,
+            bit_depth:         None,
+            content_hash:      None,
+            size:              0,
+            mtime:             0,
+            companions:        Vec::new(),
+        }
+    }
+
+    // Short summary of where the file came from, for the duplicate listing:
+    // "[BluRay H264 10bit FLAC Coalgirls]".
+    pub fn release_label(&self) -> String {
+        let mut parts = vec!(format!("{:?}", self.source_media));
+        if let Some(ref codec) = self.video_codec {
+            parts.push(codec.name());
+        }
+        if let Some(depth) = self.bit_depth {
+            parts.push(format!("{}bit", depth));
+        }
+        if let Some(ref codec) = self.audio_codec {
+            parts.push(codec.name());
+        }
+        if let Some(ref group) = self.release_group {
+            parts.push(group.clone());
+        }
+
+        format!("[{}]", parts.join(" "))
+    }
+
+    pub fn is_from_release_group(&self, group: &str) -> bool {
+        match self.release_group {
+            Some(ref g) => g.to_lowercase() == group.to_lowercase(),
+            None        => false,
+        }
+    }
+}
+
+fn parse_number<T: FromStr>(file: &Path, what: &str, number: &str) -> Result<T, Error> where T::Err: fmt::Display {
+    T::from_str(number).map_err(|e| Error::Parse(file.to_path_buf(), format!("Invalid {} \"{}\": {}", what, number, e)))
+}
+
+// What makes two files "the same episode": the same show, season, and episode. The title is
+// normalized, so differences in case, punctuation and spacing don't split up a group.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone)]
+struct GroupKey {
+    pub title:   String,
+    pub season:  SeasonNum,
+    pub episode: EpisodeNum,
+}
+
+impl GroupKey {
+    pub fn for_file(file: &AnimeFile) -> GroupKey {
+        GroupKey {
+            title:   normalize_title(&file.title),
+            season:  file.season.clone(),
+            episode: file.episode.clone(),
+        }
     }
 }
 
+fn normalize_title(title: &str) -> String {
+    title.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.len() > 0)
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
 impl PartialOrd for AnimeFile {
     fn partial_cmp(&self, other: &AnimeFile) -> Option<Ordering> {
         self.file_name.partial_cmp(&other.file_name)
@@ -191,13 +473,19 @@ impl Ord for AnimeFile {
     }
 }
 
+// Parse each of the file names, which must look like episodes.
+#[cfg(test)]
+fn anime_files(names: Vec<&str>) -> Vec<AnimeFile> {
+    names.into_iter().map(|f| AnimeFile::new(PathBuf::from(f)).unwrap().unwrap()).collect()
+}
+
 #[test]
 fn animefile_sets_parts_for_episode() {
-    let file  = "./Fairy Tail 2014 - S01E01 [www][1280x720.H264AVC.AAC][HorribleSubs](6a6129cd511d56c6080d50d68dcea5011600d7f4).mkv".to_owned();
+    let file  = PathBuf::from("./Fairy Tail 2014 - S01E01 [www][1280x720.H264AVC.AAC][HorribleSubs](6a6129cd511d56c6080d50d68dcea5011600d7f4).mkv");
     let title = "Fairy Tail 2014".to_owned();
     let af = match AnimeFile::new(file.clone()) {
-        Some(a) => { a },
-        None    => { panic!("Didn't get an AnimeFile!") },
+        Ok(Some(a)) => { a },
+        _           => { panic!("Didn't get an AnimeFile!") },
     };
     println!("{:?}", af);
 
@@ -209,15 +497,20 @@ fn animefile_sets_parts_for_episode() {
     assert_eq!(Some(1280u64),          af.resolution_width);
     assert_eq!(Some(720u64),           af.resolution_height);
     assert_eq!(1u8,                    af.version);
+    assert_eq!(Some("HorribleSubs".to_owned()), af.release_group);
+    assert_eq!(Some(VideoCodec::H264), af.video_codec);
+    assert_eq!(Some(AudioCodec::AAC), af.audio_codec);
+    assert_eq!(None, af.bit_depth);
+    assert_eq!(Some("6a6129cd511d56c6080d50d68dcea5011600d7f4".to_owned()), af.content_hash);
 }
 
 #[test]
 fn animefile_sets_parts_for_trailer() {
-    let file  = "./Working`!! - S01ET9 [Blu-ray][1920x1080.H264AVC.FLAC][tlacatlc6](91938f8ec4d2affd2f5877279af7e6803b7abcf5).mkv".to_owned();
+    let file  = PathBuf::from("./Working`!! - S01ET9 [Blu-ray][1920x1080.H264AVC.FLAC][tlacatlc6](91938f8ec4d2affd2f5877279af7e6803b7abcf5).mkv");
     let title = "Working`!!".to_owned();
     let af = match AnimeFile::new(file.clone()) {
-        Some(a) => { a },
-        None    => { panic!("Didn't get an AnimeFile!") },
+        Ok(Some(a)) => { a },
+        _           => { panic!("Didn't get an AnimeFile!") },
     };
     println!("{:?}", af);
 
@@ -229,15 +522,20 @@ fn animefile_sets_parts_for_trailer() {
     assert_eq!(Some(1920u64),          af.resolution_width);
     assert_eq!(Some(1080u64),          af.resolution_height);
     assert_eq!(1u8,                    af.version);
+    assert_eq!(Some("tlacatlc6".to_owned()), af.release_group);
+    assert_eq!(Some(VideoCodec::H264), af.video_codec);
+    assert_eq!(Some(AudioCodec::FLAC), af.audio_codec);
+    assert_eq!(None, af.bit_depth);
+    assert_eq!(Some("91938f8ec4d2affd2f5877279af7e6803b7abcf5".to_owned()), af.content_hash);
 }
 
 #[test]
 fn animefile_sets_parts_for_closing() {
-    let file  = "./Zero no Tsukaima Princess no Rondo - S01EC2 [Blu-ray][1280x720.H264AVC.FLAC][Doki](bea85424422dd1465d0758b051991966eeca6574).mkv".to_owned();
+    let file  = PathBuf::from("./Zero no Tsukaima Princess no Rondo - S01EC2 [Blu-ray][1280x720.H264AVC.FLAC][Doki](bea85424422dd1465d0758b051991966eeca6574).mkv");
     let title = "Zero no Tsukaima Princess no Rondo".to_owned();
     let af = match AnimeFile::new(file.clone()) {
-        Some(a) => { a },
-        None    => { panic!("Didn't get an AnimeFile!") },
+        Ok(Some(a)) => { a },
+        _           => { panic!("Didn't get an AnimeFile!") },
     };
     println!("{:?}", af);
 
@@ -249,15 +547,20 @@ fn animefile_sets_parts_for_closing() {
     assert_eq!(Some(1280u64),          af.resolution_width);
     assert_eq!(Some(720u64),           af.resolution_height);
     assert_eq!(1u8,                    af.version);
+    assert_eq!(Some("Doki".to_owned()), af.release_group);
+    assert_eq!(Some(VideoCodec::H264), af.video_codec);
+    assert_eq!(Some(AudioCodec::FLAC), af.audio_codec);
+    assert_eq!(None, af.bit_depth);
+    assert_eq!(Some("bea85424422dd1465d0758b051991966eeca6574".to_owned()), af.content_hash);
 }
 
 #[test]
 fn animefile_sets_parts_for_opening() {
-    let file  = "./The Garden of Sinners - S01EO7 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls](8e28f917be6423ce5ee4deee1369eb4e2eb02e48).mkv".to_owned();
+    let file  = PathBuf::from("./The Garden of Sinners - S01EO7 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls](8e28f917be6423ce5ee4deee1369eb4e2eb02e48).mkv");
     let title = "The Garden of Sinners".to_owned();
     let af = match AnimeFile::new(file.clone()) {
-        Some(a) => { a },
-        None    => { panic!("Didn't get an AnimeFile!") },
+        Ok(Some(a)) => { a },
+        _           => { panic!("Didn't get an AnimeFile!") },
     };
     println!("{:?}", af);
 
@@ -269,15 +572,20 @@ fn animefile_sets_parts_for_opening() {
     assert_eq!(Some(1920u64),          af.resolution_width);
     assert_eq!(Some(1080u64),          af.resolution_height);
     assert_eq!(1u8,                    af.version);
+    assert_eq!(Some("Coalgirls".to_owned()), af.release_group);
+    assert_eq!(Some(VideoCodec::H264), af.video_codec);
+    assert_eq!(Some(AudioCodec::FLAC), af.audio_codec);
+    assert_eq!(None, af.bit_depth);
+    assert_eq!(Some("8e28f917be6423ce5ee4deee1369eb4e2eb02e48".to_owned()), af.content_hash);
 }
 
 #[test]
 fn animefile_sets_parts_for_special() {
-    let file  = "./Texhnolyze - S01ES5 [DVD][704x396.XviD.Vorbis Ogg Vorbis_][V-A](d6175eabce82902d23446af3574fdd87286368c6).mkv".to_owned();
+    let file  = PathBuf::from("./Texhnolyze - S01ES5 [DVD][704x396.XviD.Vorbis Ogg Vorbis_][V-A](d6175eabce82902d23446af3574fdd87286368c6).mkv");
     let title = "Texhnolyze".to_owned();
     let af = match AnimeFile::new(file.clone()) {
-        Some(a) => { a },
-        None    => { panic!("Didn't get an AnimeFile!") },
+        Ok(Some(a)) => { a },
+        _           => { panic!("Didn't get an AnimeFile!") },
     };
     println!("{:?}", af);
 
@@ -289,15 +597,20 @@ fn animefile_sets_parts_for_special() {
     assert_eq!(Some(704u64),           af.resolution_width);
     assert_eq!(Some(396u64),           af.resolution_height);
     assert_eq!(1u8,                    af.version);
+    assert_eq!(Some("V-A".to_owned()), af.release_group);
+    assert_eq!(Some(VideoCodec::XviD), af.video_codec);
+    assert_eq!(Some(AudioCodec::Vorbis), af.audio_codec);
+    assert_eq!(None, af.bit_depth);
+    assert_eq!(Some("d6175eabce82902d23446af3574fdd87286368c6".to_owned()), af.content_hash);
 }
 
 #[test]
 fn animefile_sets_parts_for_version() {
-    let file  = "./Fairy Tail - S01E034v2 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4".to_owned();
+    let file  = PathBuf::from("./Fairy Tail - S01E034v2 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4");
     let title = "Fairy Tail".to_owned();
     let af = match AnimeFile::new(file.clone()) {
-        Some(a) => { a },
-        None    => { panic!("Didn't get an AnimeFile!") },
+        Ok(Some(a)) => { a },
+        _           => { panic!("Didn't get an AnimeFile!") },
     };
     println!("{:?}", af);
 
@@ -309,6 +622,295 @@ fn animefile_sets_parts_for_version() {
     assert_eq!(Some(1280u64),           af.resolution_width);
     assert_eq!(Some(720u64),            af.resolution_height);
     assert_eq!(2u8,                     af.version);
+    assert_eq!(Some("Kyuubi".to_owned()), af.release_group);
+    assert_eq!(Some(VideoCodec::H264), af.video_codec);
+    assert_eq!(Some(AudioCodec::AAC), af.audio_codec);
+    assert_eq!(None, af.bit_depth);
+    assert_eq!(Some("304a75ced2d46016e3df0c8b4607f4afe4e75952".to_owned()), af.content_hash);
+}
+
+#[test]
+fn animefile_sets_codecs_for_hi10p() {
+    let file = PathBuf::from("./Mushishi - S01E05 [Blu-ray][1920x1080.H264AVC.Hi10P.FLAC][Coalgirls](0f3c7bdbba4ec1b5ca3e1d4cba1b7b1ed5f0d1c2).mkv");
+    let af = match AnimeFile::new(file.clone()) {
+        Ok(Some(a)) => { a },
+        _           => { panic!("Didn't get an AnimeFile!") },
+    };
+    println!("{:?}", af);
+
+    assert_eq!(Some(VideoCodec::H264), af.video_codec);
+    assert_eq!(Some(AudioCodec::FLAC), af.audio_codec);
+    assert_eq!(Some(10u8),             af.bit_depth);
+}
+
+#[test]
+fn animefile_sets_codecs_for_hevc() {
+    let file = PathBuf::from("./Mushishi - S02E03 [www][1920x1080.H265HEVC.10bit.Opus][Judas](2b1f9d60ac0e1d3c3e1c7d3d5aa7b9d1b8c3e0f4).mkv");
+    let af = match AnimeFile::new(file.clone()) {
+        Ok(Some(a)) => { a },
+        _           => { panic!("Didn't get an AnimeFile!") },
+    };
+    println!("{:?}", af);
+
+    assert_eq!(Some(VideoCodec::HEVC), af.video_codec);
+    assert_eq!(Some(AudioCodec::Opus), af.audio_codec);
+    assert_eq!(Some(10u8),             af.bit_depth);
+}
+
+#[test]
+fn animefile_sets_codecs_for_unknown_codecs() {
+    let file = PathBuf::from("./Mushishi - S01E07 [DVD][720x480.WMV3.WMA][AnimeOne](a4c2e4b1d8e3a5b7c9d1e3f5a7b9c1d3e5f7a9b1).wmv");
+    let af = match AnimeFile::new(file.clone()) {
+        Ok(Some(a)) => { a },
+        _           => { panic!("Didn't get an AnimeFile!") },
+    };
+    println!("{:?}", af);
+
+    assert_eq!(Some(VideoCodec::Other("WMV3".to_owned())), af.video_codec);
+    assert_eq!(Some(AudioCodec::Other("WMA".to_owned())),  af.audio_codec);
+    assert_eq!(None,                                       af.bit_depth);
+    assert_eq!(Some("AnimeOne".to_owned()),                af.release_group);
+}
+
+#[test]
+fn animefile_sets_codecs_by_tag_not_position() {
+    let audio_only = AnimeFile::new(PathBuf::from("./Mushishi - S01E05 [Blu-ray][1920x1080.FLAC][Coalgirls].mkv")).unwrap().unwrap();
+    let audio_first = AnimeFile::new(PathBuf::from("./Mushishi - S01E05 [Blu-ray][1920x1080.FLAC.Hi10P.H264][Coalgirls].mkv")).unwrap().unwrap();
+
+    assert_eq!(None,                   audio_only.video_codec);
+    assert_eq!(Some(AudioCodec::FLAC), audio_only.audio_codec);
+    assert_eq!(Some(VideoCodec::H264), audio_first.video_codec);
+    assert_eq!(Some(AudioCodec::FLAC), audio_first.audio_codec);
+    assert_eq!(Some(10u8),             audio_first.bit_depth);
+}
+
+#[test]
+fn animefile_rejects_out_of_range_numbers() {
+    let season  = PathBuf::from("./Fairy Tail - S256E01 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4");
+    let episode = PathBuf::from("./Fairy Tail - S01E65536 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4");
+    let other   = PathBuf::from("./Fairy Tail - Opening.mp4");
+
+    match AnimeFile::new(season) {
+        Err(Error::Parse(_, reason)) => assert!(reason.starts_with("Invalid season \"256\"")),
+        a                            => panic!("Expected a parse error, got {:?}", a),
+    }
+    match AnimeFile::new(episode) {
+        Err(Error::Parse(_, reason)) => assert!(reason.starts_with("Invalid episode \"65536\"")),
+        a                            => panic!("Expected a parse error, got {:?}", a),
+    }
+    assert!(AnimeFile::new(other).unwrap().is_none());
+}
+
+#[test]
+fn animefile_parses_non_utf8_names() {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+
+    // "フェアリーテイル" in Shift-JIS.
+    let name = b"./\x83t\x83F\x83A\x83\x8a\x81[\x83e\x83C\x83\x8b - S01E02 [DVD][720x480.XviD.AC3][AnimeOne].avi".to_vec();
+    let file = PathBuf::from(OsString::from_vec(name));
+    let af = match AnimeFile::new(file.clone()) {
+        Ok(Some(a)) => { a },
+        _           => { panic!("Didn't get an AnimeFile!") },
+    };
+
+    assert_eq!(file,                   af.file_name);
+    assert_eq!(EpisodeNum::Episode(2), af.episode);
+    assert_eq!(SourceMedia::DVD,       af.source_media);
+    assert!(af.title.contains('\u{FFFD}'));
+}
+
+#[test]
+fn group_files_separates_titles() {
+    let files = anime_files(vec!(
+        "./Movies & OVAs/Akira - S01E01 [Blu-ray][1920x1080.H264AVC.FLAC][THORA](63c0e3a1d47fd3d8a8d9d4f0fc3b5e2b2a9f0d11).mkv",
+        "./Movies & OVAs/Redline - S01E01 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls](0d6d2ab1e5b07f33e4f3c3a9a5e0e2b3c1d4f5a6).mkv",
+        "./Movies & OVAs/Akira - S01E01 [DVD][720x480.XviD.AC3][AnimeOne](d4b2c3e1f0a9b8c7d6e5f4a3b2c1d0e9f8a7b6c5).avi",
+        "./Movies & OVAs/Redline - S01ES1 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls](a1b2c3d4e5f60718293a4b5c6d7e8f9012345678).mkv",
+    ));
+
+    let groups = group_files(files);
+
+    assert_eq!(3, groups.len());
+    assert_eq!(2, groups[0].len());
+    assert!(groups[0].iter().all(|f| f.title == "Akira"));
+    assert_eq!(1, groups[1].len());
+    assert_eq!("Redline",              groups[1][0].title);
+    assert_eq!(EpisodeNum::Episode(1), groups[1][0].episode);
+    assert_eq!(1, groups[2].len());
+    assert_eq!(EpisodeNum::Special(1), groups[2][0].episode);
+}
+
+#[test]
+fn group_files_normalizes_titles() {
+    let files = anime_files(vec!(
+        "./Shorts/Hetalia Axis Powers - S01E01 [DVD][720x480.H264AVC.AAC][Ayako](0123456789abcdef0123456789abcdef01234567).mkv",
+        "./Shorts/hetalia  axis-powers - S01E01 [www][1280x720.H264AVC.AAC][HorribleSubs](76543210fedcba9876543210fedcba9876543210).mkv",
+        "./Shorts/Hetalia Axis Powers - S01E02 [DVD][720x480.H264AVC.AAC][Ayako](89abcdef0123456789abcdef0123456789abcdef).mkv",
+    ));
+
+    let groups = group_files(files);
+
+    assert_eq!(2, groups.len());
+    assert_eq!(2, groups[0].len());
+    assert_eq!(EpisodeNum::Episode(1), groups[0][0].episode);
+    assert_eq!(1, groups[1].len());
+    assert_eq!(EpisodeNum::Episode(2), groups[1][0].episode);
+}
+
+#[test]
+fn group_files_orders_episodes_numerically() {
+    let files = anime_files(vec!(
+        "./Fairy Tail - S01E10 [HDTV][1280x720.H264AVC.AAC][Kyuubi](0123456789abcdef0123456789abcdef01234567).mp4",
+        "./Fairy Tail - S01E2 [HDTV][1280x720.H264AVC.AAC][Kyuubi](76543210fedcba9876543210fedcba9876543210).mp4",
+    ));
+
+    let groups = group_files(files);
+
+    assert_eq!(EpisodeNum::Episode(2),  groups[0][0].episode);
+    assert_eq!(EpisodeNum::Episode(10), groups[1][0].episode);
+}
+
+#[test]
+fn parse_user_input_rejects_selections_outside_the_group() {
+    let input = |s: &str| parse_user_input(&s.to_owned(), 2);
+
+    assert_eq!(Ok(vec!("2".to_owned())), input("2"));
+    assert_eq!(Ok(vec!("1".to_owned(), "2".to_owned())), input("1-2"));
+    assert!(input("0").is_err());
+    assert!(input("3").is_err());
+    assert!(input("0-1").is_err());
+    assert!(input("2-3").is_err());
+}
+
+#[test]
+fn keepers_from_selection_checks_every_index() {
+    let selection = |s: Vec<&str>| keepers_from_selection(&s.into_iter().map(|c| c.to_owned()).collect(), 2);
+
+    assert_eq!(Ok(Some(vec!(0, 1))), selection(vec!("2", "1", "2")));
+    assert_eq!(Ok(None), selection(vec!("1", "c")));
+    assert!(selection(vec!("0")).is_err());
+    assert!(selection(vec!("3")).is_err());
+}
+
+#[test]
+fn scan_dir_attaches_companions_to_videos_that_dont_parse() {
+    use std::env;
+    use std::fs::File;
+
+    let root = env::temp_dir().join("anime-dupe-finder-scan_dir_attaches_companions_to_videos_that_dont_parse");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    File::create(root.join("Akira (1988).mkv")).unwrap();
+    File::create(root.join("Akira (1988).srt")).unwrap();
+
+    let (_, result) = scan_dir(&root, &WalkOptions::new());
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(0, result.files.len());
+    assert_eq!(1, result.unparsed_files.len());
+    assert_eq!(vec!(root.join("Akira (1988).srt")),
+               result.unparsed_files[0].companions.iter().map(|c| c.path.clone()).collect::<Vec<PathBuf>>());
+}
+
+#[test]
+fn scan_dir_only_reports_companions_without_any_video_as_orphans() {
+    use std::env;
+    use std::fs::File;
+
+    let root = env::temp_dir().join("anime-dupe-finder-scan_dir_only_reports_companions_without_any_video_as_orphans");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    File::create(root.join("Akira (1988).mkv")).unwrap();
+    File::create(root.join("Akira (1988).en.srt")).unwrap();
+    File::create(root.join("Redline (2009).srt")).unwrap();
+
+    let (_, result) = scan_dir(&root, &WalkOptions::new());
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(vec!(root.join("Redline (2009).srt")), result.orphans.iter().map(|c| c.path.clone()).collect::<Vec<PathBuf>>());
+}
+
+#[test]
+fn scan_dirs_scans_directories_under_their_real_paths() {
+    use std::env;
+    use std::fs::File;
+    use std::os::unix;
+
+    let root = env::temp_dir().join("anime-dupe-finder-scan_dirs_scans_directories_under_their_real_paths");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("Show")).unwrap();
+    File::create(root.join("Show").join("Show - S01E01 [HDTV][1280x720.H264AVC.AAC][Kyuubi].mkv")).unwrap();
+    unix::fs::symlink(root.join("Show"), root.join("Link")).unwrap();
+
+    let (tx, rx) = mpsc::channel();
+    scan_dirs(vec!(root.clone()), WalkOptions::new(), tx);
+    let results: Vec<ScanResult> = rx.iter().collect();
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(vec!(root.clone(), root.join("Show")), results.iter().map(|r| r.dir.clone()).collect::<Vec<PathBuf>>());
+    assert_eq!(vec!(root.join("Show").join("Show - S01E01 [HDTV][1280x720.H264AVC.AAC][Kyuubi].mkv")),
+               results[1].files.iter().map(|f| f.file_name.clone()).collect::<Vec<PathBuf>>());
+}
+
+#[test]
+fn scan_dirs_stops_at_max_depth() {
+    use std::env;
+    use std::fs::File;
+
+    let root = env::temp_dir().join("anime-dupe-finder-scan_dirs_stops_at_max_depth");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("Show").join("Season 1")).unwrap();
+    File::create(root.join("Show - S01E01 [HDTV][1280x720.H264AVC.AAC][Kyuubi].mkv")).unwrap();
+
+    let mut options = WalkOptions::new();
+    options.max_depth = Some(1);
+    let (tx, rx) = mpsc::channel();
+    scan_dirs(vec!(root.clone()), options, tx);
+    let results: Vec<ScanResult> = rx.iter().collect();
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(vec!(root.clone(), root.join("Show")), results.iter().map(|r| r.dir.clone()).collect::<Vec<PathBuf>>());
+    assert_eq!(1, results[0].files.len());
+}
+
+#[test]
+fn scan_dirs_sends_each_dir_once_in_order_with_any_number_of_jobs() {
+    use std::env;
+    use std::fs::File;
+
+    let root = env::temp_dir().join("anime-dupe-finder-scan_dirs_sends_each_dir_once_in_order_with_any_number_of_jobs");
+    let _ = fs::remove_dir_all(&root);
+    for dir in vec!("a/1", "a/2/x", "b", "incoming/c", "incoming/d/y") {
+        fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    // Enough files that the outer root takes longer to scan than the inner one, which then finishes
+    // first, and is found again under the outer one.
+    for i in 0..3000 {
+        File::create(root.join(format!("{}.nfo", i))).unwrap();
+        if i % 3 == 0 {
+            File::create(root.join("incoming").join(format!("{}.nfo", i))).unwrap();
+        }
+    }
+    let scan = |jobs: usize| {
+        let mut options = WalkOptions::new();
+        options.jobs = jobs;
+        options.symlinks = SymlinkPolicy::Follow;
+        let (tx, rx) = mpsc::channel();
+        scan_dirs(vec!(root.join("incoming"), root.clone()), options, tx);
+        rx.iter().map(|r| r.dir).collect::<Vec<PathBuf>>()
+    };
+
+    let one_job = scan(1);
+    let four_jobs: Vec<Vec<PathBuf>> = (0..20).map(|_| scan(4)).collect();
+    fs::remove_dir_all(&root).unwrap();
+
+    let expected: Vec<PathBuf> = vec!("", "a", "a/1", "a/2", "a/2/x", "b", "incoming", "incoming/c", "incoming/d", "incoming/d/y")
+        .into_iter().map(|d| if d == "" { root.clone() } else { root.join(d) }).collect();
+    assert_eq!(expected, one_job);
+    for dirs in four_jobs {
+        assert_eq!(expected, dirs);
+    }
 }
 
 #[cfg(not(test))]
@@ -319,106 +921,819 @@ fn main() {
         .version(crate_version!())
         .author("Jacob Helwig <jacob@technosorcery.net>")
         .about("Find duplicates in an organized anime collection")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .arg(Arg::with_name("journal")
+             .long("journal")
+             .value_name("FILE")
+             .takes_value(true)
+             .global(true)
+             .help("Record removed files in FILE instead of $XDG_DATA_HOME/anime-dupe-finder/journal."))
         .arg(Arg::with_name("directory")
              .help("Directory to recursively search for duplicates.")
              .multiple(true)
              .index(1)
-             .required(true))
+             .required_unless("apply"))
+        .arg(Arg::with_name("symlinks")
+             .long("symlinks")
+             .value_name("POLICY")
+             .takes_value(true)
+             .possible_values(SymlinkPolicy::names())
+             .global(true)
+             .help("Follow symlinks, skip them, or follow them but never scan the same directory twice (detect, the default)."))
+        .arg(Arg::with_name("jobs")
+             .short("j")
+             .long("jobs")
+             .value_name("N")
+             .takes_value(true)
+             .global(true)
+             .validator(|n| match usize::from_str(&n) {
+                 Ok(n) if n > 0 => Ok(()),
+                 _              => Err(format!("{} isn't a positive number", n)),
+             })
+             .help("Scan up to N directories at once. Results are still reported in the same order."))
+        .arg(Arg::with_name("hidden")
+             .long("hidden")
+             .global(true)
+             .help("Also look in files and directories whose names start with '.'."))
+        .arg(Arg::with_name("exclude")
+             .long("exclude")
+             .value_name("PATTERN")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .global(true)
+             .validator(|p| PathPattern::new(&p).map(|_| ()))
+             .help("Skip files and directories matching the glob PATTERN. Patterns with a '/' are matched against the whole path, the rest against the name. May be given more than once."))
+        .arg(Arg::with_name("include")
+             .long("include")
+             .value_name("PATTERN")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .global(true)
+             .validator(|p| PathPattern::new(&p).map(|_| ()))
+             .help("Only look at files matching the glob PATTERN. Directories are still searched. May be given more than once."))
+        .arg(Arg::with_name("max-depth")
+             .long("max-depth")
+             .value_name("N")
+             .takes_value(true)
+             .global(true)
+             .validator(|n| match usize::from_str(&n) {
+                 Ok(_)  => Ok(()),
+                 Err(_) => Err(format!("{} isn't a number", n)),
+             })
+             .help("Search at most N levels of directories below the ones given. 0 searches only the directories given."))
+        .arg(Arg::with_name("one-file-system")
+             .long("one-file-system")
+             .global(true)
+             .help("Don't search directories on a different file system from the directory they're in."))
+        .arg(Arg::with_name("video-ext")
+             .long("video-ext")
+             .value_name("EXTS")
+             .takes_value(true)
+             .use_delimiter(true)
+             .global(true)
+             .help(&format!("Comma separated extensions of files that might be episodes [default: {}]", walk::DEFAULT_VIDEO_EXTENSIONS.join(","))))
+        .arg(Arg::with_name("companion-ext")
+             .long("companion-ext")
+             .value_name("EXTS")
+             .takes_value(true)
+             .use_delimiter(true)
+             .global(true)
+             .help(&format!("Comma separated extensions of subtitle and audio files that go along with an episode [default: {}]", walk::DEFAULT_COMPANION_EXTENSIONS.join(","))))
         .arg(Arg::with_name("interactive")
              .short("i")
              .long("interactive")
-             .help("Ask which file(s) to keep from each set of duplicates, and delete the rest."))
+             .help("Ask which file(s) to keep from each set of duplicates, and move the rest to the trash."))
+        .arg(Arg::with_name("auto")
+             .long("auto")
+             .value_name("POLICY")
+             .takes_value(true)
+             .possible_values(KeepPolicy::names())
+             .conflicts_with("interactive")
+             .help("Keep one file from each set of duplicates as chosen by POLICY, and move the rest to the trash. Sets the policy can't decide on, or where the file it chooses doesn't match the hash in its name, are left alone."))
+        .arg(Arg::with_name("dry-run")
+             .short("n")
+             .long("dry-run")
+             .help("Show what would be removed, without removing anything."))
+        .arg(Arg::with_name("plan")
+             .long("plan")
+             .value_name("FILE")
+             .takes_value(true)
+             .help("Write what would be removed to FILE as JSON, without removing anything. Implies --dry-run."))
+        .arg(Arg::with_name("apply")
+             .long("apply")
+             .value_name("FILE")
+             .takes_value(true)
+             .conflicts_with_all(&["directory", "interactive", "auto", "dry-run", "plan", "quarantine"])
+             .help("Carry out a plan written by --plan. Sets of files that have changed since are skipped. Files are trashed, deleted or quarantined as they were when the plan was made; give --hard-delete here only if it was given then."))
+        .arg(Arg::with_name("hard-delete")
+             .long("hard-delete")
+             .help("Delete files outright, instead of moving them to the trash."))
+        .arg(Arg::with_name("quarantine")
+             .long("quarantine")
+             .value_name("DIR")
+             .takes_value(true)
+             .conflicts_with("hard-delete")
+             .help("Move files under DIR instead of to the trash, keeping their path relative to the directory searched. With more than one directory searched, each gets its own directory under DIR."))
+        .arg(Arg::with_name("release-group")
+             .short("g")
+             .long("release-group")
+             .value_name("GROUP")
+             .takes_value(true)
+             .multiple(true)
+             .number_of_values(1)
+             .help("Only report sets of duplicates that contain a file from GROUP. May be given more than once."))
+        .subcommand(SubCommand::with_name("undo")
+                    .about("Put back files removed by an earlier run, using the journal")
+                    .arg(Arg::with_name("session")
+                         .long("session")
+                         .value_name("SESSION")
+                         .takes_value(true)
+                         .help("Undo the removals from SESSION, instead of from the most recent session."))
+                    .arg(Arg::with_name("last")
+                         .long("last")
+                         .value_name("N")
+                         .takes_value(true)
+                         .conflicts_with("session")
+                         .help("Undo the last N removals, regardless of session.")))
+        .subcommand(SubCommand::with_name("report")
+                    .about("Write a CSV and/or HTML report of the duplicates in a collection")
+                    .setting(AppSettings::ArgRequiredElseHelp)
+                    .arg(Arg::with_name("directory")
+                         .help("Directory to recursively search for duplicates.")
+                         .multiple(true)
+                         .index(1)
+                         .required(true))
+                    .arg(Arg::with_name("csv")
+                         .long("csv")
+                         .value_name("FILE")
+                         .takes_value(true)
+                         .required_unless("html")
+                         .help("Write every file in every set of duplicates to FILE as CSV."))
+                    .arg(Arg::with_name("html")
+                         .long("html")
+                         .value_name("FILE")
+                         .takes_value(true)
+                         .help("Write a standalone HTML page to FILE, grouped by series and season."))
+                    .arg(Arg::with_name("across-directories")
+                         .short("a")
+                         .long("across-directories")
+                         .help("Look for duplicate episodes across all of the directories searched, instead of within each directory.")))
+        .subcommand(SubCommand::with_name("gaps")
+                    .about("List missing episodes for each show and season, along with the specials, openings, and closings found")
+                    .setting(AppSettings::ArgRequiredElseHelp)
+                    .arg(Arg::with_name("directory")
+                         .help("Directory to recursively search for episodes.")
+                         .multiple(true)
+                         .index(1)
+                         .required(true))
+                    .arg(Arg::with_name("expected")
+                         .long("expected")
+                         .value_name("FILE")
+                         .takes_value(true)
+                         .help("Read how many episodes each season should have from FILE: one line per season, with the title, season number (empty for none), and episode count separated by tabs.")))
+        .subcommand(SubCommand::with_name("orphans")
+                    .about("List subtitles, external audio, and other companion files whose video is gone")
+                    .setting(AppSettings::ArgRequiredElseHelp)
+                    .arg(Arg::with_name("directory")
+                         .help("Directory to recursively search for orphaned files.")
+                         .multiple(true)
+                         .index(1)
+                         .required(true))
+                    .arg(Arg::with_name("remove")
+                         .long("remove")
+                         .help("Move the orphaned files to the trash."))
+                    .arg(Arg::with_name("dry-run")
+                         .short("n")
+                         .long("dry-run")
+                         .requires("remove")
+                         .help("Show what would be removed, without removing anything."))
+                    .arg(Arg::with_name("hard-delete")
+                         .long("hard-delete")
+                         .requires("remove")
+                         .help("Delete the orphaned files outright, instead of moving them to the trash."))
+                    .arg(Arg::with_name("quarantine")
+                         .long("quarantine")
+                         .value_name("DIR")
+                         .takes_value(true)
+                         .requires("remove")
+                         .conflicts_with("hard-delete")
+                         .help("Move the orphaned files under DIR instead of to the trash, keeping their path relative to the directory searched. With more than one directory searched, each gets its own directory under DIR.")))
+        .arg(Arg::with_name("content")
+             .short("c")
+             .long("content")
+             .help("Also look for byte-identical files across all of the directories searched."))
+        .arg(Arg::with_name("link")
+             .long("link")
+             .value_name("TYPE")
+             .takes_value(true)
+             .possible_values(LinkKind::names())
+             .requires("content")
+             .help("Replace byte-identical files with hard or symbolic links to a single copy, instead of removing them."))
+        .arg(Arg::with_name("verify")
+             .long("verify")
+             .help("Hash each duplicate, and check it against the hash in its file name."))
+        .arg(Arg::with_name("format")
+             .long("format")
+             .value_name("FORMAT")
+             .takes_value(true)
+             .possible_values(Format::names())
+             .conflicts_with_all(&["interactive", "auto", "plan", "apply"])
+             .help("List duplicates as colored text (the default), a JSON array, or one JSON object per line."))
+        .arg(Arg::with_name("across-directories")
+             .short("a")
+             .long("across-directories")
+             .help("Look for duplicate episodes across all of the directories searched, instead of within each directory."))
         .get_matches();
 
-    let dirs = matches.values_of("directory").unwrap();
+    let mut removal_method = if matches.is_present("hard-delete") { RemovalMethod::Delete } else { RemovalMethod::Trash };
+
+    let mut journal = Journal::new(match global_value_of(&matches, "journal") {
+        Some(j) => PathBuf::from(j),
+        None    => Journal::default_path(),
+    });
+
+    if let Some(undo_matches) = matches.subcommand_matches("undo") {
+        let last = match undo_matches.value_of("last").map(|n| usize::from_str(n)) {
+            Some(Ok(n))  => Some(n),
+            Some(Err(e)) => exit_with_error(&format!("Invalid number for --last: {}", e)),
+            None         => None,
+        };
+        match journal.undo(undo_matches.value_of("session"), last) {
+            Ok(0)  => { },
+            Ok(_)  => process::exit(1),
+            Err(e) => exit_with_error(&e),
+        }
+        return;
+    }
+
+    if let Some(report_matches) = matches.subcommand_matches("report") {
+        let mut failures = Failures::new();
+        let dirs_to_search = directories_to_search(report_matches.values_of_os("directory").unwrap(), &mut failures);
+        let groups = find_duplicate_groups(dirs_to_search, walk_options(&matches), report_matches.is_present("across-directories"), &mut failures);
+
+        if let Some(f) = report_matches.value_of_os("csv") {
+            match report::write_csv(&groups, Path::new(f)) {
+                Ok(_)  => println!("{} {}", Yellow.paint("Wrote CSV report to"), Path::new(f).display()),
+                Err(e) => failures.record(Error::Write(PathBuf::from(f), e)),
+            }
+        }
+        if let Some(f) = report_matches.value_of_os("html") {
+            match report::write_html(&groups, Path::new(f)) {
+                Ok(_)  => println!("{} {}", Yellow.paint("Wrote HTML report to"), Path::new(f).display()),
+                Err(e) => failures.record(Error::Write(PathBuf::from(f), e)),
+            }
+        }
+
+        failures.print_summary();
+        if !failures.is_empty() {
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(gaps_matches) = matches.subcommand_matches("gaps") {
+        let expected = match gaps_matches.value_of_os("expected").map(|f| gaps::read_expected_counts(Path::new(f))) {
+            Some(Ok(e))  => e,
+            Some(Err(e)) => exit_with_error(&e),
+            None         => BTreeMap::new(),
+        };
+        let mut failures = Failures::new();
+        let dirs_to_search = directories_to_search(gaps_matches.values_of_os("directory").unwrap(), &mut failures);
+        let files = find_files(dirs_to_search, walk_options(&matches), &mut failures);
+
+        if let Err(e) = gaps::print_gaps(&mut io::stdout(), &gaps::collect(&files), &expected) {
+            failures.record(Error::Write(PathBuf::from("<stdout>"), e));
+        }
+
+        failures.print_summary();
+        if !failures.is_empty() {
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(orphan_matches) = matches.subcommand_matches("orphans") {
+        let mut failures = Failures::new();
+        let dirs_to_search = directories_to_search(orphan_matches.values_of_os("directory").unwrap(), &mut failures);
+        let method = if let Some(dir) = orphan_matches.value_of_os("quarantine") {
+            RemovalMethod::Quarantine(Quarantine { dir: PathBuf::from(dir), roots: dirs_to_search.clone() })
+        } else if orphan_matches.is_present("hard-delete") {
+            RemovalMethod::Delete
+        } else {
+            RemovalMethod::Trash
+        };
+        let orphans = find_orphans(dirs_to_search, walk_options(&matches), &mut failures);
+
+        let mut last_dir = None;
+        for orphan in orphans.iter() {
+            let dir = orphan.path.parent().map(|d| d.to_path_buf());
+            if dir != last_dir {
+                println!("{} {}", Yellow.paint("Found orphaned files in"), dir.as_ref().map(|d| d.display().to_string()).unwrap_or("".to_owned()));
+                last_dir = dir;
+            }
+            println!("    {} ({})", orphan.name(), report::format_bytes(orphan.size));
+        }
+        let total: u64 = orphans.iter().map(|o| o.size).sum();
+        println!("{} orphaned file(s), {}", orphans.len(), report::format_bytes(total));
+
+        if orphan_matches.is_present("remove") {
+            let entries = Plan::new().orphan_entries(&orphans, &method);
+            if orphan_matches.is_present("dry-run") {
+                plan::describe(&entries);
+            } else {
+                failures.extend(plan::execute(&entries, &method, &mut journal));
+            }
+        }
+
+        failures.print_summary();
+        if !failures.is_empty() {
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(plan_file) = matches.value_of("apply") {
+        let plan = match Plan::read_from(Path::new(plan_file)) {
+            Ok(p)  => p,
+            Err(e) => exit_with_error(&e),
+        };
+        if let Err(e) = plan.check_method(&removal_method) {
+            exit_with_error(&e);
+        }
+        let mut failures = Failures::new();
+        failures.extend(plan.apply(&removal_method, &mut journal));
+        failures.print_summary();
+        if !failures.is_empty() {
+            process::exit(1);
+        }
+        return;
+    }
+
     let interactive_mode = matches.is_present("interactive");
+    let auto_policy: Option<KeepPolicy> = match matches.value_of("auto") {
+        Some(p) => Some(KeepPolicy::from_str(p).unwrap()),
+        None    => None,
+    };
+    let plan_file = matches.value_of("plan");
+    let verify_mode = matches.is_present("verify");
+    let content_mode = matches.is_present("content");
+    let link_kind: Option<LinkKind> = matches.value_of("link").map(|k| LinkKind::from_name(k).unwrap());
+    let across_directories = matches.is_present("across-directories");
+    let mut records = match matches.value_of("format").map(|f| Format::from_name(f).unwrap()) {
+        None | Some(Format::Text) => None,
+        Some(format)              => Some(RecordWriter::new(format, io::stdout())),
+    };
+    // Both print what they do to stdout, which would end up in the middle of the records.
+    if records.is_some() && (link_kind.is_some() || matches.is_present("dry-run")) {
+        exit_with_error("--format json and ndjson can't be used with --link or --dry-run");
+    }
+    let release_groups: Vec<String> = match matches.values_of("release-group") {
+        Some(groups) => groups.map(|g| g.to_owned()).collect(),
+        None         => Vec::new(),
+    };
 
     // Need #[derive(Debug)] for clap::args::arg_matches::Values<'_>
     //info!("Dirs to check: {:?}", dirs);
 
+    let mut failures = Failures::new();
+    let dirs_to_search = directories_to_search(matches.values_of_os("directory").unwrap(), &mut failures);
+
+    if let Some(dir) = matches.value_of_os("quarantine") {
+        removal_method = RemovalMethod::Quarantine(Quarantine {
+            dir:   PathBuf::from(dir),
+            roots: dirs_to_search.clone(),
+        });
+    }
+
+    let mut resolver = Resolver {
+        interactive: interactive_mode,
+        auto_policy: auto_policy,
+        dry_run:     matches.is_present("dry-run") || plan_file.is_some(),
+        method:      removal_method,
+        journal:     journal,
+        plan:        Plan::new(),
+        failures:    failures,
+    };
+
+    let (tx, rx) = mpsc::channel();
+
+    let options = walk_options(&matches);
+    thread::spawn(move || { scan_dirs(dirs_to_search, options, tx) });
+
+    let mut stats = Stats::new();
+    let mut all_files = Vec::new();
+    let mut all_unparsed_files = Vec::new();
+    for scan_result in rx.iter() {
+        stats.add_scan(&scan_result);
+        let ScanResult { dir: current_dir, files: new_files, unparsed_files, errors, .. } = scan_result;
+        resolver.failures.extend(errors);
+        if content_mode || across_directories {
+            all_files.extend_from_slice(&new_files);
+        }
+        if content_mode {
+            all_unparsed_files.extend(unparsed_files.into_iter());
+        }
+        if across_directories {
+            continue;
+        }
+        let grouped_files = group_files(new_files);
+
+        let episodes_with_dupes = grouped_files.iter()
+            .filter(|g| g.len() > 1)
+            .filter(|g| has_release_group(g, &release_groups))
+            .enumerate();
+        for (index, episode_files) in episodes_with_dupes {
+            stats.add_group(episode_files);
+            match records {
+                Some(ref mut r) => r.write(output::episode_group_record(Some(&current_dir), episode_files, verify_mode)),
+                None            => {
+                    if index == 0 {
+                        println!("{} {}:", Yellow.paint("Found episodes with dupes in"), current_dir.display());
+                    }
+                    print_episode_files(episode_files, false, verify_mode);
+                },
+            }
+            resolver.resolve(episode_files, ranking::recommended_keeper(episode_files));
+        }
+    }
+
+    if across_directories {
+        let grouped_files = group_files(all_files.clone());
+
+        let episodes_with_dupes = grouped_files.iter()
+            .filter(|g| g.len() > 1)
+            .filter(|g| has_release_group(g, &release_groups))
+            .enumerate();
+        for (index, episode_files) in episodes_with_dupes {
+            stats.add_group(episode_files);
+            match records {
+                Some(ref mut r) => r.write(output::episode_group_record(None, episode_files, verify_mode)),
+                None            => {
+                    if index == 0 {
+                        println!("{}", Yellow.paint("Found episodes with dupes across directories:"));
+                    }
+                    print_episode_files(episode_files, true, verify_mode);
+                },
+            }
+            resolver.resolve(episode_files, ranking::recommended_keeper(episode_files));
+        }
+    }
+
+    if content_mode {
+        // Anything removed while going through the episode duplicates is no longer a candidate.
+        all_files.extend(all_unparsed_files.into_iter());
+        all_files.retain(|f| f.file_name.exists());
+
+        for (index, identical_files) in content::find_identical(all_files).iter().enumerate() {
+            stats.add_group(identical_files);
+            match records {
+                Some(ref mut r) => r.write(output::identical_group_record(identical_files, verify_mode)),
+                None            => {
+                    if index == 0 {
+                        println!("{}", Yellow.paint("Found byte-identical files:"));
+                    }
+                    println!("  {} bytes:", identical_files[0].size);
+                    let mut i = 1;
+                    for file in identical_files.iter() {
+                        println!("    {} {}", Blue.bold().paint(format!("{})", i)), file.file_name.display());
+                        for companion in file.companions.iter() {
+                            println!("       {} {}", Blue.paint("with"), companion.name());
+                        }
+                        i += 1;
+                    }
+                },
+            }
+            match link_kind {
+                Some(kind) => resolver.link(identical_files, kind),
+                None       => resolver.resolve(identical_files, None),
+            }
+        }
+    }
+
+    // Keep stdout to just the records when something else is reading it.
+    let printed = match records {
+        Some(ref mut r) => {
+            if let Err(e) = r.finish() {
+                resolver.failures.record(Error::Write(PathBuf::from("<stdout>"), e));
+            }
+            stats.print(&mut io::stderr()).map_err(|e| ("<stderr>", e))
+        },
+        None            => stats.print(&mut io::stdout()).map_err(|e| ("<stdout>", e)),
+    };
+    if let Err((stream, e)) = printed {
+        resolver.failures.record(Error::Write(PathBuf::from(stream), e));
+    }
+
+    if let Some(f) = plan_file {
+        match resolver.plan.write_to(Path::new(f)) {
+            Ok(_)  => println!("{} {}", Yellow.paint("Wrote plan to"), f),
+            Err(e) => resolver.failures.record(Error::Write(PathBuf::from(f), e)),
+        }
+    }
+
+    resolver.failures.print_summary();
+    if !resolver.failures.is_empty() {
+        process::exit(1);
+    }
+}
+
+// For problems that mean there's nothing useful left to do.
+#[cfg(not(test))]
+fn exit_with_error(message: &str) -> ! {
+    let _ = writeln!(io::stderr(), "{} {}", Red.bold().paint("Error:"), message);
+    process::exit(1);
+}
+
+#[cfg(not(test))]
+fn directories_to_search(dirs: OsValues, failures: &mut Failures) -> Vec<PathBuf> {
     let mut dirs_to_search = Vec::new();
     for dir in dirs {
-        let path = Path::new(&dir);
+        let path = Path::new(dir);
         if path.is_dir() {
-            match path.to_str() {
-                Some(p) => dirs_to_search.push(p.to_owned()),
-                None    => panic!("Unable to convert Path to str: {:?}", path),
-            }
+            dirs_to_search.push(path.to_path_buf());
         } else {
-            panic!("ERROR: Not a directory: {}", path.display());
+            failures.record(Error::NotADirectory(path.to_path_buf()));
         }
     }
     dirs_to_search.sort();
     dirs_to_search.dedup();
 
+    dirs_to_search
+}
+
+// Scan everything before returning the sets of duplicate episodes, for when nothing needs to happen
+// as each directory is scanned.
+#[cfg(not(test))]
+fn find_duplicate_groups(dirs_to_search: Vec<PathBuf>, options: WalkOptions, across_directories: bool, failures: &mut Failures) -> Vec<Vec<AnimeFile>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || { scan_dirs(dirs_to_search, options, tx) });
+
+    let mut groups = Vec::new();
+    let mut all_files = Vec::new();
+    for scan_result in rx.iter() {
+        failures.extend(scan_result.errors);
+        if across_directories {
+            all_files.extend(scan_result.files.into_iter());
+        } else {
+            groups.extend(group_files(scan_result.files).into_iter().filter(|g| g.len() > 1));
+        }
+    }
+    if across_directories {
+        groups.extend(group_files(all_files).into_iter().filter(|g| g.len() > 1));
+    }
+
+    groups
+}
+
+// Every episode in every directory searched.
+#[cfg(not(test))]
+fn find_files(dirs_to_search: Vec<PathBuf>, options: WalkOptions, failures: &mut Failures) -> Vec<AnimeFile> {
     let (tx, rx) = mpsc::channel();
+    thread::spawn(move || { scan_dirs(dirs_to_search, options, tx) });
+
+    let mut files = Vec::new();
+    for scan_result in rx.iter() {
+        failures.extend(scan_result.errors);
+        files.extend(scan_result.files.into_iter());
+    }
 
-    thread::spawn(move || { scan_dirs(dirs_to_search, tx) });
+    files
+}
+
+// Companion files, in every directory searched, that don't go with any video.
+#[cfg(not(test))]
+fn find_orphans(dirs_to_search: Vec<PathBuf>, options: WalkOptions, failures: &mut Failures) -> Vec<Companion> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || { scan_dirs(dirs_to_search, options, tx) });
 
+    let mut orphans = Vec::new();
     for scan_result in rx.iter() {
-        let (current_dir, new_files) = scan_result;
-        let grouped_files = group_files(new_files);
+        failures.extend(scan_result.errors);
+        orphans.extend(scan_result.orphans.into_iter());
+    }
 
-        let episodes_with_dupes = grouped_files.iter().filter(|g| g.len() > 1).enumerate();
-        for (index, episode_files) in episodes_with_dupes {
-            if index == 0 {
-                println!("{} {}:", Yellow.paint("Found episodes with dupes in"), current_dir);
+    orphans
+}
+
+#[cfg(not(test))]
+fn walk_options(matches: &ArgMatches) -> WalkOptions {
+    let mut options = WalkOptions::new();
+    if let Some(policy) = global_value_of(matches, "symlinks") {
+        options.symlinks = SymlinkPolicy::from_name(policy).unwrap();
+    }
+    options.hidden = global_is_present(matches, "hidden");
+    if let Some(jobs) = global_value_of(matches, "jobs") {
+        options.jobs = usize::from_str(jobs).unwrap();
+    }
+    options.exclude = global_values_of(matches, "exclude").iter().map(|p| PathPattern::new(p).unwrap()).collect();
+    options.include = global_values_of(matches, "include").iter().map(|p| PathPattern::new(p).unwrap()).collect();
+    if let Some(depth) = global_value_of(matches, "max-depth") {
+        options.max_depth = Some(usize::from_str(depth).unwrap());
+    }
+    options.one_file_system = global_is_present(matches, "one-file-system");
+    let video_extensions = global_values_of(matches, "video-ext");
+    if video_extensions.len() > 0 {
+        options.video_extensions = extension_list(video_extensions);
+    }
+    let companion_extensions = global_values_of(matches, "companion-ext");
+    if companion_extensions.len() > 0 {
+        options.companion_extensions = extension_list(companion_extensions);
+    }
+
+    options
+}
+
+// Extensions are matched without the '.', ignoring case.
+#[cfg(not(test))]
+fn extension_list(extensions: Vec<&str>) -> Vec<String> {
+    extensions.iter().map(|e| e.trim_left_matches('.').to_lowercase()).filter(|e| e.len() > 0).collect()
+}
+
+// Global arguments end up in the matches for whichever subcommand they were given after.
+#[cfg(not(test))]
+fn global_value_of<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    match matches.value_of(name) {
+        Some(v) => Some(v),
+        None    => matches.subcommand().1.and_then(|m| m.value_of(name)),
+    }
+}
+
+#[cfg(not(test))]
+fn global_values_of<'a>(matches: &'a ArgMatches, name: &str) -> Vec<&'a str> {
+    let mut values: Vec<&str> = matches.values_of(name).map(|v| v.collect()).unwrap_or(Vec::new());
+    if let Some(m) = matches.subcommand().1 {
+        values.extend(m.values_of(name).map(|v| v.collect()).unwrap_or(Vec::new()));
+    }
+    values
+}
+
+#[cfg(not(test))]
+fn global_is_present(matches: &ArgMatches, name: &str) -> bool {
+    matches.is_present(name) || matches.subcommand().1.map(|m| m.is_present(name)).unwrap_or(false)
+}
+
+fn has_release_group(files: &Vec<AnimeFile>, release_groups: &Vec<String>) -> bool {
+    release_groups.len() == 0 || files.iter().any(|f| release_groups.iter().any(|r| f.is_from_release_group(r)))
+}
+
+fn print_episode_files(episode_files: &Vec<AnimeFile>, show_directory: bool, verify_mode: bool) {
+    println!("  {} {:?}:", episode_files[0].title, episode_files[0].episode);
+    let keeper = ranking::recommended_keeper(episode_files);
+    let mut i = 1;
+    for file in episode_files.iter() {
+        let marker = if keeper == Some(i - 1) { format!(" {}", Green.bold().paint("(keep)")) } else { "".to_owned() };
+        if show_directory {
+            let name = match file.file_name.file_name() {
+                Some(n) => n.to_string_lossy().into_owned(),
+                None    => file.file_name.display().to_string(),
+            };
+            let dir = match file.file_name.parent() {
+                Some(d) => d.display().to_string(),
+                None    => "".to_owned(),
+            };
+            println!("    {} {} {}{}", Blue.bold().paint(format!("{})", i)), Yellow.paint(file.release_label()), name, marker);
+            println!("       {} {}", Blue.paint("in"), dir);
+        } else {
+            println!("    {} {} {}{}", Blue.bold().paint(format!("{})", i)), Yellow.paint(file.release_label()), file.file_name.display(), marker);
+        }
+        for companion in file.companions.iter() {
+            println!("       {} {}", Blue.paint("with"), companion.name());
+        }
+        if verify_mode {
+            match checksum::verify(file) {
+                Verification::Intact        => println!("       {}", Green.paint("Intact")),
+                Verification::NoHash        => println!("       {}", Yellow.paint("No hash in file name")),
+                Verification::Corrupt(h)    => println!("       {} contents hash to {}", Red.bold().paint("CORRUPT:"), h),
+                Verification::Unreadable(e) => println!("       {} {}", Red.bold().paint("Unable to read:"), e),
             }
-            println!("  {:?}:", episode_files[0].episode);
-            let mut i = 1;
-            for file in episode_files.iter() {
-                println!("    {} {}", Blue.bold().paint(format!("{})", i)), file.file_name);
-                i += 1;
+        }
+        i += 1;
+    }
+}
+
+// What to do about each set of duplicates once it has been listed.
+struct Resolver {
+    interactive: bool,
+    auto_policy: Option<KeepPolicy>,
+    dry_run:     bool,
+    method:      RemovalMethod,
+    journal:     Journal,
+    plan:        Plan,
+    failures:    Failures,
+}
+
+impl Resolver {
+    fn resolve(&mut self, files_to_consider: &Vec<AnimeFile>, recommended: Option<usize>) {
+        let (keepers, reason) = if self.interactive {
+            match prompt_for_files_to_keep(files_to_consider, recommended) {
+                Ok(Some(k)) => (k, "Selected interactively".to_owned()),
+                Ok(None)    => return,
+                Err(e)      => {
+                    self.failures.record(e);
+                    return;
+                },
             }
-            if interactive_mode {
-                prompt_and_remove_files(episode_files);
+        } else if let Some(policy) = self.auto_policy {
+            let k = match policy.choose_keeper(files_to_consider) {
+                Ok(k)  => k,
+                Err(e) => {
+                    println!("      {} {}", Yellow.bold().paint("Skipping:"), e);
+                    return;
+                },
+            };
+            let keeper = &files_to_consider[k].file_name;
+            if let Err(e) = policy::check_keeper(&files_to_consider[k]) {
+                println!("      {} {} can't be kept: {}", Yellow.bold().paint("Skipping:"), keeper.display(), e);
+                self.failures.record(Error::Unverified(keeper.clone(), e));
+                return;
             }
+            println!("      {} {}", Green.bold().paint("Keeping:"), keeper.display());
+            (vec!(k), format!("Chosen by the {:?} policy", policy))
+        } else {
+            return;
+        };
+        if keepers.len() == 0 || keepers.iter().any(|&k| k >= files_to_consider.len()) {
+            println!("      {} no valid file to keep", Yellow.bold().paint("Skipping:"));
+            return;
+        }
+
+        let entries = self.plan.entries_for_group(files_to_consider, &keepers, &reason, &self.method);
+        self.carry_out(entries);
+    }
+
+    // Byte-identical files don't need to be removed: keep the first one, and make the rest links to
+    // it.
+    fn link(&mut self, identical_files: &Vec<AnimeFile>, kind: LinkKind) {
+        let entries = self.plan.link_entries_for_group(identical_files, 0, kind);
+        self.carry_out(entries);
+    }
+
+    fn carry_out(&mut self, entries: Vec<PlanEntry>) {
+        if self.dry_run {
+            plan::describe(&entries);
+            self.plan.entries.extend(entries.into_iter());
+        } else {
+            self.failures.extend(plan::execute(&entries, &self.method, &mut self.journal));
         }
     }
 }
 
-fn prompt_and_remove_files(files_to_consider: &Vec<AnimeFile>) {
-    let commands;
+// Ask which of the files to keep. Returns None if the user chose to skip the group, or there's no
+// more input to read.
+fn prompt_for_files_to_keep(files_to_consider: &Vec<AnimeFile>, recommended: Option<usize>) -> Result<Option<Vec<usize>>, Error> {
+    let prompt = match recommended {
+        Some(k) => format!("Select file(s) to keep ('c' to skip group, Enter to keep {}): ", k + 1),
+        None    => "Select file(s) to keep ('c' to skip group): ".to_owned(),
+    };
 
     loop {
         let mut user_input = String::new();
 
-        print!("    {}", Green.bold().paint("Select file(s) to keep ('c' to skip group): "));
-        io::stdout().flush().ok().expect("Could not flush stdout");
+        print!("    {}", Green.bold().paint(prompt.clone()));
+        let _ = io::stdout().flush();
 
         let parsed_input = match io::stdin().read_line(&mut user_input) {
-            Ok(_)  => parse_user_input(&user_input, files_to_consider.len()),
-            Err(e) => panic!("Error reading user input: {}", e),
+            Ok(0)  => {
+                println!("");
+                return Ok(None);
+            },
+            Ok(_)  => {
+                match recommended {
+                    Some(k) if user_input.trim() == "" => Ok(vec!(format!("{}", k + 1))),
+                    _                                  => parse_user_input(&user_input, files_to_consider.len()),
+                }
+            },
+            Err(e) => return Err(Error::Input(e)),
         };
 
-        match parsed_input {
-            Ok(i)  => {
-                commands = i;
-                break;
-            },
+        match parsed_input.and_then(|commands| keepers_from_selection(&commands, files_to_consider.len())) {
+            Ok(k)  => return Ok(k),
             Err(e) => {
                 println!("      {} {}", Red.paint("Invalid selection:"), e);
                 continue;
             },
         }
-
     }
+}
 
+// Turn the selection into the indexes of the files to keep, or None if the group is to be skipped.
+fn keepers_from_selection(commands: &Vec<String>, files_to_consider_len: usize) -> Result<Option<Vec<usize>>, String> {
     debug!("Commands: {:?}", commands);
-    let mut files_to_remove = files_to_consider.clone();
-    // We need to process the commands in reverse order, so we're removing things from the Vec from
-    // the tail (which keeps the indexes stable).
-    for command in commands.iter().rev() {
+    let mut keepers = Vec::new();
+    for command in commands.iter() {
         debug!("Executing command: {}", command);
-        if command == "c" { return; }
-        files_to_remove.remove(usize::from_str(&command).unwrap() - 1);
-    }
-    for file in files_to_remove {
-        match fs::remove_file(&file.file_name) {
-            Ok(_)  => println!("      {} {}", Red.bold().paint("Removed:"), file.file_name),
-            Err(e) => panic!("Failed to remove {}: {}", file.file_name, e),
+        if command == "c" { return Ok(None); }
+        match usize::from_str(command).ok().and_then(|n| n.checked_sub(1)) {
+            Some(k) if k < files_to_consider_len => keepers.push(k),
+            _                                    => return Err(format!("{} isn't one of the files", command)),
         }
     }
+    keepers.sort();
+    keepers.dedup();
+
+    Ok(Some(keepers))
 }
 
 fn parse_user_input(input: &String, files_to_consider_len: usize) -> Result<Vec<String>, String> {
@@ -444,7 +1759,7 @@ fn parse_user_input(input: &String, files_to_consider_len: usize) -> Result<Vec<
 
 fn expand_ranges(input: &str, files_to_consider_len: usize) -> Result<Vec<String>, String> {
     let mut result = Vec::new();
-    let max_index = (files_to_consider_len + 1) as u64;
+    let max_index = files_to_consider_len as u64;
 
     if input == "c" || input == "C" {
         result.push("c".to_owned());
@@ -466,52 +1781,45 @@ fn expand_ranges(input: &str, files_to_consider_len: usize) -> Result<Vec<String
             mem::swap(&mut low, &mut high);
         }
 
-        if high > max_index {
+        if low == 0 {
+            Err("Selections start at 1".to_owned())
+        } else if high > max_index {
             Err(format!("{} is greater than the max index ({})", high, max_index))
         } else {
             Ok((low..high + 1).map(|x| format!("{}", x) ).collect())
         }
     } else {
         match u64::from_str(input) {
-            Ok(u) => {
-                if u > max_index {
-                    return Err(format!("{} is greater than the max index ({})", u, max_index));
-                }
-            },
-            Err(_) => { },
+            Ok(0)                 => return Err("Selections start at 1".to_owned()),
+            Ok(u) if u > max_index => return Err(format!("{} is greater than the max index ({})", u, max_index)),
+            Ok(_)                 => { },
+            Err(e)                => return Err(format!("{}: {}", input, e)),
         }
 
         Ok(vec!(input.to_owned()))
     }
 }
 
+// Seconds since the epoch the file was last modified.
+fn modification_time(metadata: &fs::Metadata) -> io::Result<u64> {
+    metadata.modified().map(|t| t.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0))
+}
+
 fn group_files(files: Vec<AnimeFile>) -> Vec<Vec<AnimeFile>> {
     let mut grouped_files: Vec<Vec<AnimeFile>> = Vec::new();
 
-    let mut file_groups = HashMap::new();
+    let mut file_groups: HashMap<GroupKey, Vec<AnimeFile>> = HashMap::new();
 
     for file in files.iter() {
-        let hash_key = format!("{:?} {:?}", file.season, file.episode);
-        if !file_groups.contains_key(&hash_key) {
-            let group_vec: Vec<AnimeFile> = Vec::new();
-            file_groups.insert(hash_key.clone(), group_vec);
-        }
-
-        match file_groups.get_mut(&hash_key) {
-            Some(ref mut group) => group.push(file.clone()),
-            None                => { },
-        }
+        file_groups.entry(GroupKey::for_file(file)).or_insert(Vec::new()).push(file.clone());
     }
 
-    let mut groups = Vec::new();
-    for group in file_groups.keys() {
-        groups.push(group.clone());
-    }
+    let mut groups: Vec<GroupKey> = file_groups.keys().cloned().collect();
     groups.sort();
     for group in groups.iter() {
         let file_vec = match file_groups.get(group) {
             Some(g) => g,
-            None    => panic!("Error retrieving file group: {}", group)
+            None    => panic!("Error retrieving file group: {:?}", group)
         };
         grouped_files.push(file_vec.clone());
     }
@@ -519,95 +1827,226 @@ fn group_files(files: Vec<AnimeFile>) -> Vec<Vec<AnimeFile>> {
     grouped_files
 }
 
-fn scan_dirs(dirs_to_search: Vec<String>, tx: Sender<(String, Vec<AnimeFile>)>) {
-    let mut search_dir_queue = dirs_to_search.clone();
+// What was found in a single directory.
+struct ScanResult {
+    dir:            PathBuf,
+    files:          Vec<AnimeFile>,
+    // Video files that didn't parse as episodes, only for finding byte-identical files.
+    unparsed_files: Vec<AnimeFile>,
+    errors:         Vec<Error>,
+    // How many files were looked at, including the ones that didn't turn out to be episodes.
+    files_seen:     usize,
+    // Video files that didn't parse as episodes.
+    unparsed:       usize,
+    // Files that were neither videos nor companions, by extension.
+    skipped:        BTreeMap<String, usize>,
+    // Companion files that don't go with any of the videos.
+    orphans:        Vec<Companion>,
+}
+
+// Directories are scanned by a pool of options.jobs workers, but the results are sent on in sorted
+// order, across everything found so far, so the output comes out the same every time.
+//
+// Subdirectories are only queued once their parent's results have been sent on. Every path sorts
+// after its parent, so once the smallest directory that's queued, being scanned, or scanned is done,
+// nothing still to be found can come before it, and it's safe to send.
+//
+// The directories given are at depth 0, and their subdirectories at depth 1, and so on, for
+// options.max_depth.
+fn scan_dirs(dirs_to_search: Vec<PathBuf>, options: WalkOptions, tx: Sender<ScanResult>) {
+    let (work_tx, work_rx) = mpsc::channel::<PathBuf>();
+    let (done_tx, done_rx) = mpsc::channel::<(Vec<PathBuf>, ScanResult)>();
+    let work_rx = Arc::new(Mutex::new(work_rx));
+    for _ in 0..options.jobs {
+        let (work_rx, done_tx, options) = (work_rx.clone(), done_tx.clone(), options.clone());
+        thread::spawn(move || { scan_worker(work_rx, done_tx, options) });
+    }
+
+    let mut depths: HashMap<PathBuf, usize> = dirs_to_search.iter().map(|d| (d.clone(), 0)).collect();
+    let real_roots: Vec<PathBuf> = dirs_to_search.iter().filter_map(|d| fs::canonicalize(d).ok()).collect();
+    let mut search_dir_queue: BTreeSet<PathBuf> = dirs_to_search.into_iter().collect();
+    let mut in_progress: BTreeSet<PathBuf> = BTreeSet::new();
+    let mut scanned: BTreeMap<PathBuf, (Vec<PathBuf>, ScanResult)> = BTreeMap::new();
+    let mut sent: HashSet<PathBuf> = HashSet::new();
+    let mut visited = Visited::new();
 
     loop {
-        let current_dir;
-        if search_dir_queue.len() > 0 {
-            current_dir = search_dir_queue.remove(0);
-        } else {
+        while in_progress.len() < options.jobs {
+            let next_dir = match search_dir_queue.iter().next() {
+                Some(d) => d.clone(),
+                None    => break,
+            };
+            search_dir_queue.remove(&next_dir);
+            in_progress.insert(next_dir.clone());
+            if work_tx.send(next_dir).is_err() {
+                return;
+            }
+        }
+
+        if in_progress.len() == 0 && scanned.len() == 0 {
             return;
         }
+        match done_rx.recv() {
+            Ok((new_dirs, scan_result)) => {
+                in_progress.remove(&scan_result.dir);
+                scanned.insert(scan_result.dir.clone(), (new_dirs, scan_result));
+            },
+            Err(_) => return,
+        }
 
-        info!("Scanning: {}", current_dir);
-        let (new_dirs, new_files) = scan_dir(&current_dir);
+        loop {
+            let next_dir = match scanned.keys().next() {
+                Some(d) => d.clone(),
+                None    => break,
+            };
+            let waiting_on_earlier = search_dir_queue.iter().next().map(|d| *d < next_dir).unwrap_or(false)
+                || in_progress.iter().next().map(|d| *d < next_dir).unwrap_or(false);
+            if waiting_on_earlier {
+                break;
+            }
+
+            let (new_dirs, scan_result) = scanned.remove(&next_dir).unwrap();
+            let depth = depths.remove(&next_dir).unwrap_or(0);
+            sent.insert(next_dir.clone());
+            if options.symlinks == SymlinkPolicy::Detect && !visited.first_visit(&next_dir) {
+                info!("Already scanned, skipping: {}", next_dir.display());
+                continue;
+            }
+            if options.max_depth.map(|max| depth < max).unwrap_or(true) {
+                // A directory given to search can also be found under another one.
+                for new_dir in new_dirs {
+                    if in_progress.contains(&new_dir) || scanned.contains_key(&new_dir) || sent.contains(&new_dir) {
+                        continue;
+                    }
+                    if options.symlinks == SymlinkPolicy::Detect && walk::is_link_into(&new_dir, &real_roots) {
+                        info!("Symlink to a directory that's searched anyway, skipping: {}", new_dir.display());
+                        continue;
+                    }
+                    depths.entry(new_dir.clone()).or_insert(depth + 1);
+                    search_dir_queue.insert(new_dir);
+                }
+            } else if new_dirs.len() > 0 {
+                info!("Not searching below, too deep: {}", next_dir.display());
+            }
+
+            if scan_result.files.len() > 0 {
+                info!("Found some files in: {}", next_dir.display());
+            }
+            if tx.send(scan_result).is_err() {
+                return;
+            }
+        }
+    }
+}
 
-        match new_dirs {
-            None       => { },
-            Some(dirs) => {
-                search_dir_queue.extend_from_slice(&dirs);
-                search_dir_queue.sort();
-                search_dir_queue.dedup();
+fn scan_worker(work_rx: Arc<Mutex<mpsc::Receiver<PathBuf>>>, done_tx: Sender<(Vec<PathBuf>, ScanResult)>, options: WalkOptions) {
+    loop {
+        let dir = match work_rx.lock() {
+            Ok(rx) => match rx.recv() {
+                Ok(d)  => d,
+                Err(_) => return,
             },
+            Err(_) => return,
         };
 
-        match new_files {
-            None => { },
-            Some(files) => {
-                info!("Found some files in: {}", current_dir);
-                tx.send((current_dir, files)).unwrap();
-            },
+        info!("Scanning: {}", dir.display());
+        if done_tx.send(scan_dir(&dir, &options)).is_err() {
+            return;
         }
     }
 }
 
-fn scan_dir(dir: &String) -> (Option<Vec<String>>, Option<Vec<AnimeFile>>) {
-    let re = regex!(r"\.(?i:srt|ass|ssa|ac3|idx|sub|dts|flac|mka)$");
-    let mut new_dirs  = Vec::new();
+// Anything that can't be read is skipped, and returned with the rest of the results.
+fn scan_dir(dir: &Path, options: &WalkOptions) -> (Vec<PathBuf>, ScanResult) {
     let mut new_files = Vec::new();
-
-    let glob_str = match Path::new(&Pattern::escape(&dir)).join("*").into_os_string().into_string() {
-        Ok(s)  => s,
-        Err(e) => panic!("Unable to get a string of {:?}", e),
-    };
-    debug!("Listing: {}", glob_str);
-    for entry in glob::glob(&glob_str).unwrap() {
-        debug!("Entry: {:?}", entry);
-        let path = match entry {
-            Ok(p)  => p,
-            Err(e) => panic!("Unable to process glob match: {}", e),
-        };
-
+    let mut unparsed_files = Vec::new();
+    // Only so their companions aren't taken for orphans.
+    let mut unreadable_files = Vec::new();
+    let mut files_seen = 0;
+    let mut unparsed   = 0;
+    let mut skipped    = BTreeMap::new();
+    let mut companions = Vec::new();
+
+    debug!("Listing: {}", dir.display());
+    let listing = walk::list_dir(dir, options);
+    let mut errors = listing.errors;
+
+    for path in listing.files {
         info!("Found: {}", path.display());
-        if path.is_dir() {
-            debug!("Adding directory to scan.");
-            new_dirs.push(path);
-        } else if path.is_file() {
-            debug!("Processing file");
-            let path_string = match path.clone().into_os_string().into_string() {
-                Ok(s)  => { s },
-                Err(e) => {
-                    panic!("Unable to convert path ({}) to str: {:?}",
-                           path.display(),
-                           e);
-                },
-            };
-
-            match re.captures(&path_string) {
-                Some(_) => { /* Nothing to do: Support file */ },
-                None    => {
-                    let anime_file = match AnimeFile::new(path_string.clone()) {
-                        Some(a) => { a },
-                        None    => { continue; },
-                    };
-                    new_files.push(anime_file);
+        files_seen += 1;
+        match options.file_kind(&path) {
+            FileKind::Video     => { },
+            FileKind::Companion => {
+                match fs::metadata(&path).and_then(|m| modification_time(&m).map(|t| (m.len(), t))) {
+                    Ok((size, mtime)) => companions.push(Companion { path: path, size: size, mtime: mtime }),
+                    Err(e)            => errors.push(Error::Unreadable(path, e)),
                 }
-            };
+                continue;
+            },
+            FileKind::Other     => {
+                debug!("Skipping, not a video: {}", path.display());
+                *skipped.entry(walk::extension(&path).unwrap_or("".to_owned())).or_insert(0) += 1;
+                continue;
+            },
         }
-    }
-
-    new_dirs.sort();
-    new_files.sort();
 
-    let mut new_string_dirs = Vec::new();
-    for dir in new_dirs {
-        match dir.into_os_string().into_string() {
-            Ok(s)  => new_string_dirs.push(s),
-            Err(e) => panic!("Problem converting PathBuf into String: {:?}", e),
+        let (mut anime_file, parsed) = match AnimeFile::new(path.clone()) {
+            Ok(Some(a)) => { (a, true) },
+            Ok(None)    => {
+                unparsed += 1;
+                (AnimeFile::unparsed(path.clone()), false)
+            },
+            Err(e)      => {
+                unparsed += 1;
+                errors.push(e);
+                (AnimeFile::unparsed(path.clone()), false)
+            },
+        };
+        let metadata = match fs::metadata(&path) {
+            Ok(m)  => m,
+            Err(e) => {
+                errors.push(Error::Unreadable(path.clone(), e));
+                unreadable_files.push(anime_file);
+                continue;
+            },
+        };
+        anime_file.size = metadata.len();
+        anime_file.mtime = match modification_time(&metadata) {
+            Ok(t)  => t,
+            Err(e) => {
+                errors.push(Error::Unreadable(path.clone(), e));
+                unreadable_files.push(anime_file);
+                continue;
+            },
+        };
+        if parsed {
+            new_files.push(anime_file);
+        } else {
+            unparsed_files.push(anime_file);
         }
     }
 
-    (if new_string_dirs.len() == 0 { None } else { Some(new_string_dirs) },
-     if new_files.len()       == 0 { None } else { Some(new_files)       })
+    new_files.sort();
+    unparsed_files.sort();
+    // Videos that aren't episodes have companions too, which go wherever they go when they turn out
+    // to be identical to another file. Companions of videos that couldn't be read are left alone.
+    let (parsed_count, unparsed_count) = (new_files.len(), unparsed_files.len());
+    new_files.extend(unparsed_files.into_iter());
+    new_files.extend(unreadable_files.into_iter());
+    let orphans = companion::associate(&mut new_files, companions);
+    new_files.truncate(parsed_count + unparsed_count);
+    let unparsed_files = new_files.split_off(parsed_count);
+
+    let scan_result = ScanResult {
+        dir:            dir.to_path_buf(),
+        files:          new_files,
+        unparsed_files: unparsed_files,
+        errors:         errors,
+        files_seen:     files_seen,
+        unparsed:       unparsed,
+        skipped:        skipped,
+        orphans:        orphans,
+    };
+
+    (listing.dirs, scan_result)
 }
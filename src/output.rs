@@ -0,0 +1,236 @@
+use rustc_serialize::json::{Json, ToJson};
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+// Traits
+use std::io::Write;
+
+use checksum;
+use checksum::Verification;
+use plan::path_to_json;
+use ranking;
+#[cfg(test)] use anime_files;
+use AnimeFile;
+use EpisodeNum;
+use SeasonNum;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    // Colored listing, for people.
+    Text,
+    // One JSON array of groups.
+    Json,
+    // One JSON object per group, per line.
+    Ndjson,
+}
+
+impl Format {
+    pub fn names() -> &'static [&'static str] {
+        &["text", "json", "ndjson"]
+    }
+
+    pub fn from_name(name: &str) -> Result<Format, String> {
+        match name {
+            "text"   => Ok(Format::Text),
+            "json"   => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            _        => Err(format!("Unknown output format: {}", name)),
+        }
+    }
+}
+
+// Writes group records to stdout as they're found, so whatever is reading them doesn't have to wait
+// for the whole scan to finish. After the first write that fails nothing more is written, and the
+// error comes back from finish.
+pub struct RecordWriter<W: Write> {
+    format:  Format,
+    out:     W,
+    written: usize,
+    error:   Option<io::Error>,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub fn new(format: Format, out: W) -> RecordWriter<W> {
+        RecordWriter { format: format, out: out, written: 0, error: None }
+    }
+
+    pub fn write(&mut self, record: Json) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = match self.format {
+            Format::Json if self.written == 0 => writeln!(self.out, "[\n{}", record),
+            Format::Json                      => writeln!(self.out, ",\n{}", record),
+            _                                 => writeln!(self.out, "{}", record),
+        }.and_then(|_| self.out.flush());
+        match result {
+            Ok(_)  => self.written += 1,
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    // Close off the array, if there is one.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        match self.format {
+            Format::Json if self.written == 0 => try!(writeln!(self.out, "[]")),
+            Format::Json                      => try!(writeln!(self.out, "]")),
+            _                                 => { },
+        }
+        self.out.flush()
+    }
+}
+
+impl ToJson for SeasonNum {
+    fn to_json(&self) -> Json {
+        match *self {
+            SeasonNum::Season(s) => s.to_json(),
+            SeasonNum::NoSeason  => Json::Null,
+        }
+    }
+}
+
+impl ToJson for EpisodeNum {
+    fn to_json(&self) -> Json {
+        let (kind, number) = match *self {
+            EpisodeNum::Episode(n)      => ("episode", n),
+            EpisodeNum::Opening(n)      => ("opening", n),
+            EpisodeNum::Closing(n)      => ("closing", n),
+            EpisodeNum::Special(n)      => ("special", n),
+            EpisodeNum::Trailer(n)      => ("trailer", n),
+            EpisodeNum::OtherEpisode(n) => ("other", n),
+            EpisodeNum::NoEpisode       => return Json::Null,
+        };
+
+        let mut obj = BTreeMap::new();
+        obj.insert("type".to_owned(),   kind.to_json());
+        obj.insert("number".to_owned(), number.to_json());
+        Json::Object(obj)
+    }
+}
+
+impl ToJson for AnimeFile {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("path".to_owned(),              path_to_json(&self.file_name));
+        obj.insert("title".to_owned(),             self.title.to_json());
+        obj.insert("season".to_owned(),            self.season.to_json());
+        obj.insert("episode".to_owned(),           self.episode.to_json());
+        obj.insert("source_media".to_owned(),      format!("{:?}", self.source_media).to_json());
+        obj.insert("resolution_width".to_owned(),  self.resolution_width.to_json());
+        obj.insert("resolution_height".to_owned(), self.resolution_height.to_json());
+        obj.insert("version".to_owned(),           self.version.to_json());
+        obj.insert("release_group".to_owned(),     self.release_group.to_json());
+        obj.insert("video_codec".to_owned(),       self.video_codec.as_ref().map(|c| c.name()).to_json());
+        obj.insert("audio_codec".to_owned(),       self.audio_codec.as_ref().map(|c| c.name()).to_json());
+        obj.insert("bit_depth".to_owned(),         self.bit_depth.to_json());
+        obj.insert("content_hash".to_owned(),      self.content_hash.to_json());
+        obj.insert("size".to_owned(),              self.size.to_json());
+        obj.insert("mtime".to_owned(),             self.mtime.to_json());
+        obj.insert("companions".to_owned(),        Json::Array(self.companions.iter().map(|c| path_to_json(&c.path)).collect()));
+        Json::Object(obj)
+    }
+}
+
+// A set of files that are the same episode. directory is None when the files were grouped across
+// all of the directories searched.
+pub fn episode_group_record(directory: Option<&Path>, files: &Vec<AnimeFile>, verify: bool) -> Json {
+    let mut obj = BTreeMap::new();
+    obj.insert("kind".to_owned(),        "episode".to_json());
+    obj.insert("directory".to_owned(),   directory.map(|d| path_to_json(d)).unwrap_or(Json::Null));
+    obj.insert("title".to_owned(),       files[0].title.to_json());
+    obj.insert("season".to_owned(),      files[0].season.to_json());
+    obj.insert("episode".to_owned(),     files[0].episode.to_json());
+    obj.insert("recommended".to_owned(), ranking::recommended_keeper(files).to_json());
+    obj.insert("files".to_owned(),       files_to_json(files, verify));
+    Json::Object(obj)
+}
+
+// A set of byte-identical files.
+pub fn identical_group_record(files: &Vec<AnimeFile>, verify: bool) -> Json {
+    let mut obj = BTreeMap::new();
+    obj.insert("kind".to_owned(),  "identical".to_json());
+    obj.insert("size".to_owned(),  files[0].size.to_json());
+    obj.insert("files".to_owned(), files_to_json(files, verify));
+    Json::Object(obj)
+}
+
+fn files_to_json(files: &Vec<AnimeFile>, verify: bool) -> Json {
+    Json::Array(files.iter().map(|file| {
+        let mut json = file.to_json();
+        if verify {
+            if let Json::Object(ref mut obj) = json {
+                obj.insert("verification".to_owned(), verification_to_json(checksum::verify(file)));
+            }
+        }
+        json
+    }).collect())
+}
+
+fn verification_to_json(verification: Verification) -> Json {
+    match verification {
+        Verification::Intact        => "intact".to_json(),
+        Verification::NoHash        => "no_hash".to_json(),
+        Verification::Corrupt(h)    => format!("corrupt: contents hash to {}", h).to_json(),
+        Verification::Unreadable(e) => format!("unreadable: {}", e).to_json(),
+    }
+}
+
+#[test]
+fn episode_group_record_includes_every_field() {
+    let files = anime_files(vec!(
+        "./Fairy Tail - S01E034v2 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4",
+        "./Fairy Tail - S01E034 [Blu-ray][1920x1080.H264AVC.Hi10P.FLAC][Coalgirls](8e28f917be6423ce5ee4deee1369eb4e2eb02e48).mkv",
+    ));
+
+    let record = episode_group_record(Some(Path::new(".")), &files, false);
+
+    assert_eq!(Some("."),       record.find("directory").and_then(|d| d.as_string()));
+    assert_eq!(Some(1),         record.find("season").and_then(|s| s.as_u64()));
+    assert_eq!(Some("episode"), record.find_path(&["episode", "type"]).and_then(|t| t.as_string()));
+    assert_eq!(Some(34),        record.find_path(&["episode", "number"]).and_then(|n| n.as_u64()));
+    assert_eq!(Some(1),         record.find("recommended").and_then(|r| r.as_u64()));
+
+    let second = &record.find("files").and_then(|f| f.as_array()).unwrap()[1];
+    assert_eq!(Some("BluRay"),    second.find("source_media").and_then(|m| m.as_string()));
+    assert_eq!(Some(1920),        second.find("resolution_width").and_then(|w| w.as_u64()));
+    assert_eq!(Some("H264"),      second.find("video_codec").and_then(|c| c.as_string()));
+    assert_eq!(Some("FLAC"),      second.find("audio_codec").and_then(|c| c.as_string()));
+    assert_eq!(Some(10),          second.find("bit_depth").and_then(|b| b.as_u64()));
+    assert_eq!(Some("Coalgirls"), second.find("release_group").and_then(|g| g.as_string()));
+    assert_eq!(Some(0),           second.find("size").and_then(|s| s.as_u64()));
+    assert_eq!(Some(0),           second.find("mtime").and_then(|m| m.as_u64()));
+}
+
+#[cfg(test)]
+struct FullWriter;
+
+#[cfg(test)]
+impl Write for FullWriter {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::Other, "No space left on device"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn record_writer_closes_the_array_and_returns_write_errors() {
+    let mut records = RecordWriter::new(Format::Json, Vec::new());
+    records.write(Json::U64(1));
+    records.write(Json::U64(2));
+    assert!(records.finish().is_ok());
+    assert_eq!("[\n1\n,\n2\n]\n", String::from_utf8(records.out).unwrap());
+
+    let mut records = RecordWriter::new(Format::Ndjson, FullWriter);
+    records.write(Json::U64(1));
+    records.write(Json::U64(2));
+    assert_eq!(0, records.written);
+    assert!(records.finish().is_err());
+}
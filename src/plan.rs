@@ -0,0 +1,513 @@
+use ansi_term::Colour::{Red, Yellow};
+use rustc_serialize::json::{Json, ToJson};
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Traits
+use std::io::{Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+
+use companion::Companion;
+use error::Error;
+use journal::Journal;
+use link;
+use link::LinkKind;
+use modification_time;
+use removal;
+use removal::RemovalMethod;
+#[cfg(test)] use anime_files;
+use AnimeFile;
+
+// Version 1 plans said "delete" for any removal, leaving it to --apply to say how.
+const PLAN_FORMAT_VERSION: u64 = 2;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Action {
+    Keep,
+    Trash,
+    Delete,
+    Move(PathBuf),
+    // Replace the file with a link to the given path.
+    Link(PathBuf, LinkKind),
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match *self {
+            Action::Keep       => "keep",
+            Action::Trash      => "trash",
+            Action::Delete     => "delete",
+            Action::Move(_)    => "move",
+            Action::Link(_, _) => "link",
+        }
+    }
+}
+
+// What to do with a single file, and why. The size and modification time are what the file looked
+// like when the decision was made, so a plan can refuse to act on files that have changed since.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PlanEntry {
+    pub group:  u64,
+    pub path:   PathBuf,
+    pub action: Action,
+    pub reason: String,
+    pub size:   u64,
+    pub mtime:  u64,
+}
+
+impl ToJson for PlanEntry {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("group".to_owned(),  self.group.to_json());
+        obj.insert("path".to_owned(),   path_to_json(&self.path));
+        obj.insert("action".to_owned(), self.action.name().to_json());
+        match self.action {
+            Action::Move(ref destination)          => {
+                obj.insert("destination".to_owned(), path_to_json(destination));
+            },
+            Action::Link(ref destination, ref kind) => {
+                obj.insert("destination".to_owned(), path_to_json(destination));
+                obj.insert("link".to_owned(), kind.name().to_json());
+            },
+            _                                       => { },
+        }
+        obj.insert("reason".to_owned(), self.reason.to_json());
+        obj.insert("size".to_owned(),   self.size.to_json());
+        obj.insert("mtime".to_owned(),  self.mtime.to_json());
+        Json::Object(obj)
+    }
+}
+
+impl PlanEntry {
+    fn from_json(json: &Json) -> Result<PlanEntry, String> {
+        let string_field = |name: &str| -> Result<String, String> {
+            match json.find(name).and_then(|j| j.as_string()) {
+                Some(s) => Ok(s.to_owned()),
+                None    => Err(format!("Missing or invalid \"{}\" in plan entry: {}", name, json)),
+            }
+        };
+        let path_field = |name: &str| -> Result<PathBuf, String> {
+            match json.find(name).and_then(|j| path_from_json(j)) {
+                Some(p) => Ok(p),
+                None    => Err(format!("Missing or invalid \"{}\" in plan entry: {}", name, json)),
+            }
+        };
+        let u64_field = |name: &str| -> Result<u64, String> {
+            match json.find(name).and_then(|j| j.as_u64()) {
+                Some(u) => Ok(u),
+                None    => Err(format!("Missing or invalid \"{}\" in plan entry: {}", name, json)),
+            }
+        };
+
+        Ok(PlanEntry {
+            group:  try!(u64_field("group")),
+            path:   try!(path_field("path")),
+            action: match &try!(string_field("action"))[..] {
+                "keep"   => Action::Keep,
+                "trash"  => Action::Trash,
+                "delete" => Action::Delete,
+                "move"   => Action::Move(try!(path_field("destination"))),
+                "link"   => Action::Link(try!(path_field("destination")), try!(LinkKind::from_name(&try!(string_field("link"))))),
+                a        => return Err(format!("Unknown action \"{}\" in plan entry: {}", a, json)),
+            },
+            reason: try!(string_field("reason")),
+            size:   try!(u64_field("size")),
+            mtime:  try!(u64_field("mtime")),
+        })
+    }
+
+    // Make sure the file is still the one the plan was made for.
+    fn check_unchanged(&self) -> Result<(), Error> {
+        let metadata = match fs::metadata(&self.path) {
+            Ok(m)  => m,
+            Err(e) => return Err(Error::Unreadable(self.path.clone(), e)),
+        };
+        let mtime = match modification_time(&metadata) {
+            Ok(t)  => t,
+            Err(e) => return Err(Error::Unreadable(self.path.clone(), e)),
+        };
+
+        if metadata.len() != self.size {
+            Err(Error::Changed(self.path.clone(), format!("size changed from {} to {}", self.size, metadata.len())))
+        } else if mtime != self.mtime {
+            Err(Error::Changed(self.path.clone(), format!("modification time changed from {} to {}", self.mtime, mtime)))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Plan {
+    pub entries: Vec<PlanEntry>,
+    next_group:  u64,
+}
+
+impl ToJson for Plan {
+    fn to_json(&self) -> Json {
+        let mut obj = BTreeMap::new();
+        obj.insert("version".to_owned(), PLAN_FORMAT_VERSION.to_json());
+        obj.insert("actions".to_owned(), self.entries.to_json());
+        Json::Object(obj)
+    }
+}
+
+impl Plan {
+    pub fn new() -> Plan {
+        Plan { entries: Vec::new(), next_group: 1 }
+    }
+
+    // Turn a choice of which files to keep out of a set of duplicates into plan entries: everything
+    // that isn't kept gets removed the way the method says, along with its companions.
+    pub fn entries_for_group(&mut self, files: &Vec<AnimeFile>, keepers: &Vec<usize>, keep_reason: &str, method: &RemovalMethod) -> Vec<PlanEntry> {
+        let group = self.next_group;
+        self.next_group += 1;
+
+        let kept_names: Vec<String> = keepers.iter().map(|&k| files[k].file_name.display().to_string()).collect();
+        let delete_reason = format!("Duplicate of {}", kept_names.join(", "));
+
+        let mut entries = Vec::new();
+        for (i, file) in files.iter().enumerate() {
+            if keepers.contains(&i) {
+                entries.push(PlanEntry {
+                    group:  group,
+                    path:   file.file_name.clone(),
+                    action: Action::Keep,
+                    reason: keep_reason.to_owned(),
+                    size:   file.size,
+                    mtime:  file.mtime,
+                });
+                continue;
+            }
+
+            entries.push(PlanEntry {
+                group:  group,
+                path:   file.file_name.clone(),
+                action: removal_action(method, &file.file_name),
+                reason: delete_reason.clone(),
+                size:   file.size,
+                mtime:  file.mtime,
+            });
+            for companion in file.companions.iter() {
+                entries.push(PlanEntry {
+                    group:  group,
+                    path:   companion.path.clone(),
+                    action: removal_action(method, &companion.path),
+                    reason: format!("Companion of {}", file.file_name.display()),
+                    size:   companion.size,
+                    mtime:  companion.mtime,
+                });
+            }
+        }
+
+        entries
+    }
+
+    // Remove companion files that don't have a video to go with any more.
+    pub fn orphan_entries(&mut self, orphans: &Vec<Companion>, method: &RemovalMethod) -> Vec<PlanEntry> {
+        let group = self.next_group;
+        self.next_group += 1;
+
+        orphans.iter().map(|orphan| {
+            PlanEntry {
+                group:  group,
+                path:   orphan.path.clone(),
+                action: removal_action(method, &orphan.path),
+                reason: "No video to go with".to_owned(),
+                size:   orphan.size,
+                mtime:  orphan.mtime,
+            }
+        }).collect()
+    }
+
+    // Replace every file but the keeper with a link to it. Files that are already links to the
+    // keeper are left alone.
+    pub fn link_entries_for_group(&mut self, files: &Vec<AnimeFile>, keeper: usize, kind: LinkKind) -> Vec<PlanEntry> {
+        let group = self.next_group;
+        self.next_group += 1;
+
+        let keeper_path = files[keeper].file_name.clone();
+        files.iter().enumerate().map(|(i, file)| {
+            let (action, reason) = if i == keeper {
+                (Action::Keep, "Link target".to_owned())
+            } else if link::is_same_file(&keeper_path, &file.file_name) {
+                (Action::Keep, format!("Already linked to {}", keeper_path.display()))
+            } else {
+                (Action::Link(keeper_path.clone(), kind), format!("Identical to {}", keeper_path.display()))
+            };
+            PlanEntry {
+                group:  group,
+                path:   file.file_name.clone(),
+                action: action,
+                reason: reason,
+                size:   file.size,
+                mtime:  file.mtime,
+            }
+        }).collect()
+    }
+
+    pub fn write_to(&self, path: &Path) -> io::Result<()> {
+        let mut file = try!(File::create(path));
+        try!(write!(file, "{}", self.to_json().pretty()));
+        try!(writeln!(file, ""));
+        Ok(())
+    }
+
+    pub fn read_from(path: &Path) -> Result<Plan, String> {
+        let mut contents = String::new();
+        match File::open(path).and_then(|mut f| f.read_to_string(&mut contents)) {
+            Ok(_)  => { },
+            Err(e) => return Err(format!("Unable to read {}: {}", path.display(), e)),
+        }
+
+        let json = match Json::from_str(&contents) {
+            Ok(j)  => j,
+            Err(e) => return Err(format!("Unable to parse {}: {}", path.display(), e)),
+        };
+        match json.find("version").and_then(|v| v.as_u64()) {
+            Some(PLAN_FORMAT_VERSION) => { },
+            Some(1)                   => return Err(format!("{} doesn't say whether to trash or delete files; make the plan again", path.display())),
+            v                         => return Err(format!("Unsupported plan version: {:?}", v)),
+        }
+        let actions = match json.find("actions").and_then(|a| a.as_array()) {
+            Some(a) => a,
+            None    => return Err(format!("No actions in {}", path.display())),
+        };
+
+        let mut plan = Plan::new();
+        for action in actions {
+            let entry = try!(PlanEntry::from_json(action));
+            if entry.group >= plan.next_group {
+                plan.next_group = entry.group + 1;
+            }
+            plan.entries.push(entry);
+        }
+
+        Ok(plan)
+    }
+
+    // The plan says how each file is to be removed, so --hard-delete has to agree with it: a plan made
+    // to trash files is never applied as a hard delete, and one made with --hard-delete is only
+    // applied with it.
+    pub fn check_method(&self, method: &RemovalMethod) -> Result<(), String> {
+        let hard_delete = *method == RemovalMethod::Delete;
+        for entry in self.entries.iter() {
+            match entry.action {
+                Action::Trash if hard_delete   => return Err(format!("The plan moves {} to the trash, but --hard-delete was given", entry.path.display())),
+                Action::Delete if !hard_delete => return Err(format!("The plan deletes {} outright; give --hard-delete to apply it", entry.path.display())),
+                _                              => { },
+            }
+        }
+        Ok(())
+    }
+
+    // Run a plan read back from disk. A group is only acted on if every one of its files, kept or
+    // not, is still exactly as it was when the plan was made.
+    pub fn apply(&self, method: &RemovalMethod, journal: &mut Journal) -> Vec<Error> {
+        let mut groups: BTreeMap<u64, Vec<PlanEntry>> = BTreeMap::new();
+        for entry in self.entries.iter() {
+            groups.entry(entry.group).or_insert(Vec::new()).push(entry.clone());
+        }
+
+        let mut failures = Vec::new();
+        for (group, entries) in groups {
+            let changed: Vec<Error> = entries.iter().filter_map(|e| e.check_unchanged().err()).collect();
+            if changed.len() > 0 {
+                println!("{} {}:", Yellow.bold().paint("Skipping group"), group);
+                for reason in changed.iter() {
+                    println!("      {}", reason);
+                }
+                failures.extend(changed.into_iter());
+                continue;
+            }
+
+            failures.extend(execute(&entries, method, journal).into_iter());
+        }
+
+        failures
+    }
+}
+
+// Carry out the entries, recording every removal in the journal. Returns what went wrong.
+pub fn execute(entries: &Vec<PlanEntry>, method: &RemovalMethod, journal: &mut Journal) -> Vec<Error> {
+    let mut failures = Vec::new();
+
+    for entry in entries.iter() {
+        let path = entry.path.as_path();
+        if entry.action == Action::Keep {
+            continue;
+        }
+        let (size, hash) = Journal::describe_file(path);
+        let (action, past_tense, result) = match entry.action {
+            Action::Keep                   => continue,
+            Action::Trash                  => {
+                let trash = RemovalMethod::Trash;
+                (trash.journal_action(), trash.past_tense(), trash.remove(path))
+            },
+            Action::Delete                 => {
+                let delete = RemovalMethod::Delete;
+                (delete.journal_action(), delete.past_tense(), delete.remove(path))
+            },
+            Action::Move(ref destination)  => {
+                let past_tense = match *method {
+                    RemovalMethod::Quarantine(_) => method.past_tense(),
+                    _                            => "Moved:",
+                };
+                ("move", past_tense, removal::move_file(path, destination).map(|_| Some(destination.clone())))
+            },
+            Action::Link(ref target, kind) => {
+                ("link", "Linked:", link::replace_with_link(target, path, kind).map(|_| Some(target.clone())))
+            },
+        };
+
+        match result {
+            Ok(destination) => {
+                match destination {
+                    Some(ref d) => println!("      {} {} -> {}", Red.bold().paint(past_tense), entry.path.display(), d.display()),
+                    None        => println!("      {} {}", Red.bold().paint(past_tense), entry.path.display()),
+                }
+                if let Err(e) = journal.record(action, path, destination.as_ref().map(|d| d.as_path()), size, hash, None) {
+                    println!("      {} {}", Red.bold().paint("Unable to write to the journal:"), e);
+                    failures.push(Error::Journal(entry.path.clone(), e));
+                }
+            },
+            Err(e) => {
+                let failed = match entry.action {
+                    Action::Trash      => "Failed to trash",
+                    Action::Move(_)    => "Failed to move",
+                    Action::Link(_, _) => "Failed to link",
+                    _                  => "Failed to remove",
+                };
+                println!("      {} {}: {}", Red.bold().paint(failed), entry.path.display(), e);
+                failures.push(Error::Removal(entry.path.clone(), e));
+            },
+        }
+    }
+
+    failures
+}
+
+// Quarantined files are moved to where they'll be kept; everything else is removed in place.
+fn removal_action(method: &RemovalMethod, path: &Path) -> Action {
+    match *method {
+        RemovalMethod::Trash             => Action::Trash,
+        RemovalMethod::Delete            => Action::Delete,
+        RemovalMethod::Quarantine(ref q) => Action::Move(q.destination_for(path)),
+    }
+}
+
+// Show what the entries would do, without doing any of it.
+pub fn describe(entries: &Vec<PlanEntry>) {
+    for entry in entries.iter() {
+        match entry.action {
+            Action::Keep                  => { },
+            Action::Trash                 => println!("      {} {}", Red.paint("Would trash:"), entry.path.display()),
+            Action::Delete                => println!("      {} {}", Red.paint("Would remove:"), entry.path.display()),
+            Action::Move(ref destination) => println!("      {} {} -> {}", Red.paint("Would move:"), entry.path.display(), destination.display()),
+            Action::Link(ref target, _)   => println!("      {} {} -> {}", Red.paint("Would link:"), entry.path.display(), target.display()),
+        }
+    }
+}
+
+// Paths are written as strings when they're valid UTF-8, and as arrays of bytes otherwise, so that
+// files with names in other encodings can still be found again.
+pub fn path_to_json(path: &Path) -> Json {
+    match path.to_str() {
+        Some(s) => s.to_json(),
+        None    => path.as_os_str().as_bytes().to_vec().to_json(),
+    }
+}
+
+pub fn path_from_json(json: &Json) -> Option<PathBuf> {
+    match *json {
+        Json::String(ref s)    => Some(PathBuf::from(s)),
+        Json::Array(ref bytes) => {
+            let mut raw = Vec::new();
+            for b in bytes.iter() {
+                match b.as_u64() {
+                    Some(b) if b < 256 => raw.push(b as u8),
+                    _                  => return None,
+                }
+            }
+            Some(PathBuf::from(OsString::from_vec(raw)))
+        },
+        _                      => None,
+    }
+}
+
+#[test]
+fn plan_round_trips_through_json() {
+    let file = "./Fairy Tail - S01E034v2 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4";
+    let files = anime_files(vec!(file, &file.replace("v2", "")));
+
+    let mut plan = Plan::new();
+    let entries = plan.entries_for_group(&files, &vec!(0), "Highest version", &RemovalMethod::Trash);
+    plan.entries.extend(entries.into_iter());
+
+    let json = plan.to_json();
+    let mut read_back = Plan::new();
+    for action in json.find("actions").unwrap().as_array().unwrap() {
+        read_back.entries.push(PlanEntry::from_json(action).unwrap());
+    }
+
+    assert_eq!(plan.entries, read_back.entries);
+    assert_eq!(Action::Keep,   read_back.entries[0].action);
+    assert_eq!(Action::Trash,  read_back.entries[1].action);
+    assert_eq!(format!("Duplicate of {}", file), read_back.entries[1].reason);
+}
+
+#[test]
+fn check_method_refuses_flags_that_disagree_with_the_plan() {
+    let file = "./Fairy Tail - S01E034v2 [HDTV][1280x720.H264AVC.AAC][Kyuubi].mp4";
+    let files = anime_files(vec!(file, &file.replace("v2", "")));
+
+    let mut trash_plan = Plan::new();
+    let entries = trash_plan.entries_for_group(&files, &vec!(0), "Highest version", &RemovalMethod::Trash);
+    trash_plan.entries.extend(entries.into_iter());
+    let mut delete_plan = Plan::new();
+    let entries = delete_plan.entries_for_group(&files, &vec!(0), "Highest version", &RemovalMethod::Delete);
+    delete_plan.entries.extend(entries.into_iter());
+
+    assert_eq!(Action::Delete, delete_plan.entries[1].action);
+    assert!(trash_plan.check_method(&RemovalMethod::Trash).is_ok());
+    assert!(trash_plan.check_method(&RemovalMethod::Delete).is_err());
+    assert!(delete_plan.check_method(&RemovalMethod::Delete).is_ok());
+    assert!(delete_plan.check_method(&RemovalMethod::Trash).is_err());
+}
+
+#[test]
+fn entries_for_group_removes_companions_with_their_video() {
+    use removal::Quarantine;
+
+    let file = "/srv/anime/Fairy Tail - S01E034v2 [HDTV][1280x720.H264AVC.AAC][Kyuubi].mp4";
+    let mut files = anime_files(vec!(file, &file.replace("v2", "")));
+    for f in files.iter_mut() {
+        let subtitles = f.file_name.with_extension("ass");
+        f.companions.push(Companion { path: subtitles, size: 10, mtime: 20 });
+    }
+    let quarantine = RemovalMethod::Quarantine(Quarantine { dir: PathBuf::from("/srv/quarantine"), roots: vec!(PathBuf::from("/srv/anime")) });
+
+    let entries = Plan::new().entries_for_group(&files, &vec!(0), "Highest version", &quarantine);
+
+    assert_eq!(3, entries.len());
+    assert_eq!(Action::Keep, entries[0].action);
+    assert_eq!(PathBuf::from("/srv/anime/Fairy Tail - S01E034 [HDTV][1280x720.H264AVC.AAC][Kyuubi].ass"), entries[2].path);
+    assert_eq!(Action::Move(PathBuf::from("/srv/quarantine/Fairy Tail - S01E034 [HDTV][1280x720.H264AVC.AAC][Kyuubi].ass")), entries[2].action);
+    assert_eq!(format!("Companion of {}", file.replace("v2", "")), entries[2].reason);
+    assert_eq!((10, 20), (entries[2].size, entries[2].mtime));
+}
+
+#[test]
+fn path_round_trips_through_json() {
+    let utf8 = PathBuf::from("/anime/Fairy Tail - S01E01.mkv");
+    let shift_jis = PathBuf::from(OsString::from_vec(b"/anime/\x83t\x83F\x83A\x83\x8a\x81[ - S01E01.mkv".to_vec()));
+
+    assert_eq!(Json::String("/anime/Fairy Tail - S01E01.mkv".to_owned()), path_to_json(&utf8));
+    assert_eq!(Some(utf8.clone()), path_from_json(&path_to_json(&utf8)));
+    assert_eq!(Some(shift_jis.clone()), path_from_json(&path_to_json(&shift_jis)));
+}
@@ -0,0 +1,135 @@
+use std::str::FromStr;
+
+use checksum;
+use checksum::Verification;
+use ranking;
+#[cfg(test)] use anime_files;
+use AnimeFile;
+
+// How to pick which file to keep out of a set of duplicates without asking.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KeepPolicy {
+    // Keep the file with the best quality score.
+    HighestRanked,
+    // Keep the highest version, when all of the files are from the same release group.
+    HighestVersion,
+    // Keep the most recently modified file.
+    Newest,
+}
+
+impl FromStr for KeepPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<KeepPolicy, String> {
+        match s {
+            "ranked"  => Ok(KeepPolicy::HighestRanked),
+            "version" => Ok(KeepPolicy::HighestVersion),
+            "newest"  => Ok(KeepPolicy::Newest),
+            _         => Err(format!("Unknown keep policy: {}", s)),
+        }
+    }
+}
+
+impl KeepPolicy {
+    pub fn names() -> &'static [&'static str] {
+        &["ranked", "version", "newest"]
+    }
+
+    // The index of the file to keep, or the reason the policy can't decide.
+    pub fn choose_keeper(&self, files: &[AnimeFile]) -> Result<usize, String> {
+        let keeper = match *self {
+            KeepPolicy::HighestRanked  => ranking::recommended_keeper(files),
+            KeepPolicy::HighestVersion => {
+                let first_group = files[0].release_group.clone().map(|g| g.to_lowercase());
+                if first_group.is_none() || files.iter().any(|f| f.release_group.clone().map(|g| g.to_lowercase()) != first_group) {
+                    return Err("Files are not all from the same release group".to_owned());
+                }
+                ranking::best_by(files, |f| f.version)
+            },
+            KeepPolicy::Newest         => ranking::best_by(files, |f| f.mtime),
+        };
+
+        match keeper {
+            Some(k) => Ok(k),
+            None    => Err(format!("Unable to break a tie with the {:?} policy", self)),
+        }
+    }
+}
+
+// Nothing gets removed in favor of a file that isn't what its name says it is. Files without a hash
+// in their name can't be checked, and are trusted.
+pub fn check_keeper(file: &AnimeFile) -> Result<(), String> {
+    match checksum::verify(file) {
+        Verification::Intact | Verification::NoHash => Ok(()),
+        Verification::Corrupt(h)                     => Err(format!("Contents hash to {}, not the hash in the file name", h)),
+        Verification::Unreadable(e)                  => Err(format!("Unable to read to verify: {}", e)),
+    }
+}
+
+#[test]
+fn highest_version_keeps_newer_version_from_same_group() {
+    let files = anime_files(vec!(
+        "./Fairy Tail - S01E034 [HDTV][1280x720.H264AVC.AAC][Kyuubi](0123456789abcdef0123456789abcdef01234567).mp4",
+        "./Fairy Tail - S01E034v2 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4",
+    ));
+
+    assert_eq!(Ok(1), KeepPolicy::HighestVersion.choose_keeper(&files));
+}
+
+#[test]
+fn highest_version_refuses_mixed_groups() {
+    let files = anime_files(vec!(
+        "./Fairy Tail - S01E034 [HDTV][1280x720.H264AVC.AAC][HorribleSubs](0123456789abcdef0123456789abcdef01234567).mp4",
+        "./Fairy Tail - S01E034v2 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4",
+    ));
+
+    assert!(KeepPolicy::HighestVersion.choose_keeper(&files).is_err());
+}
+
+#[test]
+fn newest_refuses_ties() {
+    let mut files = anime_files(vec!(
+        "./Fairy Tail - S01E01 [HDTV][1280x720.H264AVC.AAC][HorribleSubs](0123456789abcdef0123456789abcdef01234567).mp4",
+        "./Fairy Tail - S01E01 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls](8e28f917be6423ce5ee4deee1369eb4e2eb02e48).mkv",
+    ));
+    files[0].mtime = 1400000000;
+    files[1].mtime = 1400000000;
+
+    assert!(KeepPolicy::Newest.choose_keeper(&files).is_err());
+
+    files[0].mtime = 1500000000;
+
+    assert_eq!(Ok(0), KeepPolicy::Newest.choose_keeper(&files));
+}
+
+#[test]
+fn check_keeper_refuses_files_that_dont_match_their_hash() {
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    let root = env::temp_dir().join("anime-dupe-finder-check_keeper_refuses_files_that_dont_match_their_hash");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let contents = root.join("contents");
+    File::create(&contents).unwrap().write_all(b"episode").unwrap();
+    let name = |hash: &str| root.join(format!("Fairy Tail - S01E01 [HDTV][1280x720.H264AVC.AAC][Kyuubi]{}.mkv", hash));
+    let intact  = name(&format!("({})", checksum::sha1_file(&contents).unwrap()));
+    let corrupt = name("(0123456789abcdef0123456789abcdef01234567)");
+    let no_hash = name("");
+    let missing = name("(76543210fedcba9876543210fedcba9876543210)");
+    for path in vec!(&intact, &corrupt, &no_hash) {
+        fs::copy(&contents, path).unwrap();
+    }
+
+    let check = |path: &PathBuf| check_keeper(&AnimeFile::new(path.clone()).unwrap().unwrap());
+    let results = vec!(check(&intact), check(&corrupt), check(&no_hash), check(&missing));
+    fs::remove_dir_all(&root).unwrap();
+
+    assert!(results[0].is_ok());
+    assert!(results[1].is_err());
+    assert!(results[2].is_ok());
+    assert!(results[3].is_err());
+}
@@ -0,0 +1,123 @@
+#[cfg(test)] use anime_files;
+use AnimeFile;
+use AudioCodec;
+use SourceMedia;
+use VideoCodec;
+
+// How good a file is, compared field by field in order: source media first, then resolution,
+// version, and finally the codecs.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct QualityScore {
+    pub media:       u8,
+    pub resolution:  u64,
+    pub version:     u8,
+    pub video_codec: u8,
+    pub bit_depth:   u8,
+    pub audio_codec: u8,
+}
+
+pub fn score(file: &AnimeFile) -> QualityScore {
+    QualityScore {
+        media:       media_rank(&file.source_media),
+        resolution:  file.resolution_width.unwrap_or(0) * file.resolution_height.unwrap_or(0),
+        version:     file.version,
+        video_codec: match file.video_codec {
+            Some(ref c) => video_codec_rank(c),
+            None        => 0,
+        },
+        bit_depth:   file.bit_depth.unwrap_or(8),
+        audio_codec: match file.audio_codec {
+            Some(ref c) => audio_codec_rank(c),
+            None        => 0,
+        },
+    }
+}
+
+// The index of the file that should be kept out of a set of duplicates, or None if the best files
+// are tied.
+pub fn recommended_keeper(files: &[AnimeFile]) -> Option<usize> {
+    best_by(files, |f| score(f))
+}
+
+// The index of the file with the highest key, or None if more than one file shares it.
+pub fn best_by<K, F>(files: &[AnimeFile], key_for: F) -> Option<usize>
+    where K: Ord, F: Fn(&AnimeFile) -> K {
+    let keys: Vec<K> = files.iter().map(|f| key_for(f)).collect();
+    let best = match keys.iter().max() {
+        Some(k) => k,
+        None    => return None,
+    };
+
+    let mut best_indexes = keys.iter().enumerate().filter(|&(_, k)| k == best).map(|(i, _)| i);
+    match (best_indexes.next(), best_indexes.next()) {
+        (Some(i), None) => Some(i),
+        _               => None,
+    }
+}
+
+fn media_rank(media: &SourceMedia) -> u8 {
+    match *media {
+        SourceMedia::BluRay       => 10,
+        SourceMedia::DVD          => 9,
+        SourceMedia::HKDVD        => 8,
+        SourceMedia::HDTV         => 7,
+        SourceMedia::WWW          => 6,
+        SourceMedia::DTV          => 5,
+        SourceMedia::TV           => 4,
+        SourceMedia::LaserDisc    => 3,
+        SourceMedia::VHS          => 2,
+        SourceMedia::OtherMedia   => 1,
+        SourceMedia::UnknownMedia => 0,
+    }
+}
+
+fn video_codec_rank(codec: &VideoCodec) -> u8 {
+    match *codec {
+        VideoCodec::AV1      => 5,
+        VideoCodec::HEVC     => 4,
+        VideoCodec::H264     => 3,
+        VideoCodec::XviD     => 2,
+        VideoCodec::Other(_) => 1,
+    }
+}
+
+fn audio_codec_rank(codec: &AudioCodec) -> u8 {
+    match *codec {
+        AudioCodec::FLAC     => 6,
+        AudioCodec::Opus     => 5,
+        AudioCodec::AAC      => 4,
+        AudioCodec::AC3      => 3,
+        AudioCodec::Vorbis   => 2,
+        AudioCodec::Other(_) => 1,
+    }
+}
+
+#[test]
+fn recommended_keeper_prefers_better_media() {
+    let files = anime_files(vec!(
+        "./Fairy Tail - S01E01 [HDTV][1920x1080.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4",
+        "./Fairy Tail - S01E01 [Blu-ray][1280x720.H264AVC.FLAC][Coalgirls](8e28f917be6423ce5ee4deee1369eb4e2eb02e48).mkv",
+    ));
+
+    assert_eq!(Some(1), recommended_keeper(&files));
+}
+
+#[test]
+fn recommended_keeper_prefers_higher_version() {
+    let files = anime_files(vec!(
+        "./Fairy Tail - S01E034v2 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4",
+        "./Fairy Tail - S01E034 [HDTV][1280x720.H264AVC.AAC][Kyuubi](0123456789abcdef0123456789abcdef01234567).mp4",
+    ));
+
+    assert_eq!(Some(0), recommended_keeper(&files));
+}
+
+#[test]
+fn recommended_keeper_refuses_ties() {
+    let files = anime_files(vec!(
+        "./Fairy Tail - S01E01 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4",
+        "./Fairy Tail - S01E01 [HDTV][1280x720.H264AVC.AAC][HorribleSubs](0123456789abcdef0123456789abcdef01234567).mkv",
+    ));
+
+    assert_eq!(None, recommended_keeper(&files));
+}
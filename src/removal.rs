@@ -0,0 +1,285 @@
+use libc;
+
+use std::ffi::OsString;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+// Traits
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+
+use checksum;
+use trash;
+
+// How files that aren't being kept get gotten rid of.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RemovalMethod {
+    // Move to the freedesktop.org trash, so it can be restored.
+    Trash,
+    // Unlink the file. There's no getting it back.
+    Delete,
+    // Move under a quarantine directory, keeping the layout of the directories searched.
+    Quarantine(Quarantine),
+}
+
+impl RemovalMethod {
+    // Remove the file, returning where it was moved to, if anywhere.
+    pub fn remove(&self, path: &Path) -> io::Result<Option<PathBuf>> {
+        match *self {
+            RemovalMethod::Trash             => trash::trash_file(path).map(|p| Some(p)),
+            RemovalMethod::Delete            => fs::remove_file(path).map(|_| None),
+            RemovalMethod::Quarantine(ref q) => {
+                let destination = q.destination_for(path);
+                move_file(path, &destination).map(|_| Some(destination))
+            },
+        }
+    }
+
+    // What the journal calls this kind of removal.
+    pub fn journal_action(&self) -> &'static str {
+        match *self {
+            RemovalMethod::Trash         => "trash",
+            RemovalMethod::Delete        => "delete",
+            RemovalMethod::Quarantine(_) => "move",
+        }
+    }
+
+    pub fn past_tense(&self) -> &'static str {
+        match *self {
+            RemovalMethod::Trash         => "Trashed:",
+            RemovalMethod::Delete        => "Removed:",
+            RemovalMethod::Quarantine(_) => "Quarantined:",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Quarantine {
+    pub dir:   PathBuf,
+    // The directories being searched. Files are placed under dir relative to the outermost of these
+    // they were found in.
+    pub roots: Vec<PathBuf>,
+}
+
+impl Quarantine {
+    // Different files always get different destinations. Roots inside other roots are ignored, so
+    // every file has one place to go, and when there's more than one root left, each one gets its
+    // own directory under dir, named after it: "Show", or "Show.2" if an earlier root is also called
+    // "Show".
+    pub fn destination_for(&self, path: &Path) -> PathBuf {
+        let outermost = self.outermost_roots();
+        let found_in = outermost.iter().enumerate().find(|&(_, r)| path.strip_prefix(r).is_ok());
+
+        match found_in {
+            Some((i, root)) => {
+                let relative = path.strip_prefix(root).unwrap();
+                if outermost.len() == 1 {
+                    self.dir.join(relative)
+                } else {
+                    self.dir.join(root_dir_name(&outermost, i)).join(relative)
+                }
+            },
+            None            => {
+                warn!("{} isn't under any of the directories searched", path.display());
+                let mut destination = self.dir.clone();
+                for component in path.components() {
+                    match component {
+                        Component::Normal(c) => destination.push(c),
+                        _                    => { },
+                    }
+                }
+                destination
+            },
+        }
+    }
+
+    // The roots that aren't inside any other root, in the order given, without repeats.
+    fn outermost_roots(&self) -> Vec<&PathBuf> {
+        let mut outermost: Vec<&PathBuf> = Vec::new();
+        for root in self.roots.iter() {
+            let inside_another = self.roots.iter().any(|r| r != root && root.starts_with(r));
+            if !inside_another && !outermost.contains(&root) {
+                outermost.push(root);
+            }
+        }
+        outermost
+    }
+}
+
+fn root_dir_name(roots: &Vec<&PathBuf>, index: usize) -> OsString {
+    let name_of = |r: &PathBuf| r.file_name().map(|n| n.to_os_string()).unwrap_or(OsString::from("root"));
+    let name = name_of(roots[index]);
+    let same_name_before = roots[..index].iter().filter(|r| name_of(r) == name).count();
+
+    if same_name_before == 0 {
+        name
+    } else {
+        let mut name = name;
+        name.push(format!(".{}", same_name_before + 1));
+        name
+    }
+}
+
+// Move the file, refusing to overwrite anything. If the destination is on another file system, the
+// file is copied, the copy is checked against the original, and only then is the original removed.
+pub fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        try!(fs::create_dir_all(parent));
+    }
+
+    match rename_no_replace(from, to) {
+        Ok(_)                      => Ok(()),
+        Err(ref e) if cant_link(e) => {
+            debug!("Unable to link {} into place, copying instead", to.display());
+            copy_and_remove(from, to)
+        },
+        Err(e)                     => Err(e),
+    }
+}
+
+// The destination is on another file system, or one that doesn't do hard links.
+fn cant_link(error: &io::Error) -> bool {
+    match error.raw_os_error() {
+        Some(libc::EXDEV) | Some(libc::EPERM) | Some(libc::EOPNOTSUPP) => true,
+        _                                                              => false,
+    }
+}
+
+fn already_exists(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", path.display()))
+}
+
+// Like fs::rename, but fails if there's already something at `to`, instead of replacing it. The file
+// is hard linked to its new name, which never overwrites anything, and then unlinked from the old
+// one. Only works within a file system.
+pub fn rename_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        Ok(_)                                                  => { },
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(already_exists(to)),
+        Err(e)                                                 => return Err(e),
+    }
+
+    match fs::remove_file(from) {
+        Ok(_)  => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(to);
+            Err(e)
+        },
+    }
+}
+
+fn copy_and_remove(from: &Path, to: &Path) -> io::Result<()> {
+    // create_new, so nothing that turned up at `to` in the meantime gets overwritten.
+    let mut copy = match OpenOptions::new().write(true).create_new(true).open(to) {
+        Ok(f)                                                  => f,
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(already_exists(to)),
+        Err(e)                                                 => return Err(e),
+    };
+    let copied = File::open(from)
+        .and_then(|mut original| io::copy(&mut original, &mut copy))
+        .and_then(|_| fs::metadata(from))
+        .and_then(|m| fs::set_permissions(to, m.permissions()).and_then(|_| copy_times(&m, &copy)));
+    if let Err(e) = copied {
+        let _ = fs::remove_file(to);
+        return Err(e);
+    }
+
+    let (original, copy) = (try!(checksum::sha1_file(from)), try!(checksum::sha1_file(to)));
+    if original != copy {
+        let _ = fs::remove_file(to);
+        return Err(io::Error::new(io::ErrorKind::Other, format!("Copy of {} to {} doesn't match the original", from.display(), to.display())));
+    }
+
+    fs::remove_file(from)
+}
+
+// Give the copy the original's access and modification times, so it still looks like the same file.
+fn copy_times(original: &fs::Metadata, copy: &File) -> io::Result<()> {
+    let times = [
+        libc::timespec { tv_sec: original.atime() as libc::time_t, tv_nsec: original.atime_nsec() as libc::c_long },
+        libc::timespec { tv_sec: original.mtime() as libc::time_t, tv_nsec: original.mtime_nsec() as libc::c_long },
+    ];
+    if unsafe { libc::futimens(copy.as_raw_fd(), times.as_ptr()) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[test]
+fn quarantine_keeps_path_relative_to_root() {
+    let quarantine = Quarantine {
+        dir:   PathBuf::from("/srv/quarantine"),
+        roots: vec!(PathBuf::from("/srv/anime"), PathBuf::from("/srv/anime/incoming")),
+    };
+
+    assert_eq!(PathBuf::from("/srv/quarantine/Fairy Tail/Fairy Tail - S01E01.mkv"),
+               quarantine.destination_for(Path::new("/srv/anime/Fairy Tail/Fairy Tail - S01E01.mkv")));
+    assert_eq!(PathBuf::from("/srv/quarantine/incoming/Fairy Tail - S01E01.mkv"),
+               quarantine.destination_for(Path::new("/srv/anime/incoming/Fairy Tail - S01E01.mkv")));
+    assert_eq!(PathBuf::from("/srv/quarantine/Fairy Tail - S01E01.mkv"),
+               quarantine.destination_for(Path::new("/srv/anime/Fairy Tail - S01E01.mkv")));
+}
+
+#[test]
+fn quarantine_keeps_roots_with_the_same_name_apart() {
+    let quarantine = Quarantine {
+        dir:   PathBuf::from("/srv/quarantine"),
+        roots: vec!(PathBuf::from("/a/Show"), PathBuf::from("/b/Show"), PathBuf::from("/c/Movies")),
+    };
+
+    assert_eq!(PathBuf::from("/srv/quarantine/Show/Fairy Tail - S01E01.mkv"),
+               quarantine.destination_for(Path::new("/a/Show/Fairy Tail - S01E01.mkv")));
+    assert_eq!(PathBuf::from("/srv/quarantine/Show.2/Fairy Tail - S01E01.mkv"),
+               quarantine.destination_for(Path::new("/b/Show/Fairy Tail - S01E01.mkv")));
+    assert_eq!(PathBuf::from("/srv/quarantine/Movies/Akira.mkv"),
+               quarantine.destination_for(Path::new("/c/Movies/Akira.mkv")));
+}
+
+#[test]
+fn move_file_never_replaces_the_destination() {
+    use std::env;
+    use std::io::{Read, Write};
+
+    let root = env::temp_dir().join("anime-dupe-finder-move_file_never_replaces_the_destination");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    File::create(root.join("from")).unwrap().write_all(b"from").unwrap();
+    File::create(root.join("to")).unwrap().write_all(b"to").unwrap();
+
+    let result = move_file(&root.join("from"), &root.join("to"));
+    let mut contents = String::new();
+    File::open(root.join("to")).unwrap().read_to_string(&mut contents).unwrap();
+    let from_exists = root.join("from").exists();
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(Some(io::ErrorKind::AlreadyExists), result.err().map(|e| e.kind()));
+    assert_eq!("to", contents);
+    assert!(from_exists);
+}
+
+#[test]
+fn copy_and_remove_keeps_the_modification_time() {
+    use std::env;
+    use std::io::Write;
+
+    let root = env::temp_dir().join("anime-dupe-finder-copy_and_remove_keeps_the_modification_time");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let mut original = File::create(root.join("from")).unwrap();
+    original.write_all(b"from").unwrap();
+    let an_hour_ago = fs::metadata(root.join("from")).unwrap().mtime() - 3600;
+    let times = [libc::timespec { tv_sec: an_hour_ago as libc::time_t, tv_nsec: 0 }; 2];
+    assert_eq!(0, unsafe { libc::futimens(original.as_raw_fd(), times.as_ptr()) });
+
+    let result = copy_and_remove(&root.join("from"), &root.join("to"));
+    let copy = fs::metadata(root.join("to")).map(|m| (m.mtime(), m.mtime_nsec()));
+    let from_exists = root.join("from").exists();
+    fs::remove_dir_all(&root).unwrap();
+
+    assert!(result.is_ok());
+    assert_eq!((an_hour_ago, 0), copy.unwrap());
+    assert!(!from_exists);
+}
@@ -0,0 +1,198 @@
+// Reports on the duplicates in a collection, for people who'd rather not read terminal output: a CSV
+// of every file in every set of duplicates, and a standalone HTML page.
+use time;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+// Traits
+use std::io::Write;
+
+use normalize_title;
+use ranking;
+#[cfg(test)] use anime_files;
+use AnimeFile;
+use SeasonNum;
+
+// Which file to count as kept when working out how much space could be reclaimed: the recommended
+// keeper, or the largest file when there isn't a clear recommendation.
+pub fn assumed_keeper(files: &Vec<AnimeFile>) -> usize {
+    match ranking::recommended_keeper(files) {
+        Some(k) => k,
+        None    => files.iter().enumerate().max_by_key(|&(_, f)| f.size).map(|(i, _)| i).unwrap_or(0),
+    }
+}
+
+// How many bytes would be freed by keeping only one file out of the set.
+pub fn reclaimable_bytes(files: &Vec<AnimeFile>) -> u64 {
+    let total: u64 = files.iter().map(|f| f.size).sum();
+    total - files[assumed_keeper(files)].size
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["bytes", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, units[0])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+fn resolution(file: &AnimeFile) -> String {
+    match (file.resolution_width, file.resolution_height) {
+        (Some(w), Some(h)) => format!("{}x{}", w, h),
+        _                  => "".to_owned(),
+    }
+}
+
+pub fn season_label(season: &SeasonNum) -> String {
+    match *season {
+        SeasonNum::Season(s) => format!("Season {}", s),
+        SeasonNum::NoSeason  => "No season".to_owned(),
+    }
+}
+
+pub fn write_csv(groups: &Vec<Vec<AnimeFile>>, path: &Path) -> io::Result<()> {
+    let mut file = try!(File::create(path));
+    try!(writeln!(file, "group,series,season,episode,path,size,media,resolution,video_codec,audio_codec,release_group,version,recommended"));
+
+    for (index, files) in groups.iter().enumerate() {
+        let keeper = ranking::recommended_keeper(files);
+        for (i, f) in files.iter().enumerate() {
+            let fields = vec!(
+                format!("{}", index + 1),
+                f.title.clone(),
+                match f.season { SeasonNum::Season(s) => format!("{}", s), SeasonNum::NoSeason => "".to_owned() },
+                format!("{:?}", f.episode),
+                f.file_name.to_string_lossy().into_owned(),
+                format!("{}", f.size),
+                format!("{:?}", f.source_media),
+                resolution(f),
+                f.video_codec.as_ref().map(|c| c.name()).unwrap_or("".to_owned()),
+                f.audio_codec.as_ref().map(|c| c.name()).unwrap_or("".to_owned()),
+                f.release_group.clone().unwrap_or("".to_owned()),
+                format!("{}", f.version),
+                if keeper == Some(i) { "yes".to_owned() } else { "no".to_owned() },
+            );
+            let quoted: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+            try!(writeln!(file, "{}", quoted.join(",")));
+        }
+    }
+
+    Ok(())
+}
+
+// Quote the field if it has anything in it that would confuse a CSV reader.
+fn csv_field(field: &str) -> String {
+    if field.contains(',') || field.contains('"') || field.contains('\n') || field.contains('\r') {
+        format!("\"{}\"", field.replace("\"", "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+const HTML_STYLE: &'static str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h1 { margin-bottom: 0.2em; }
+.generated { color: #777; margin-top: 0; }
+.total { font-size: 1.2em; background: #eef6ee; padding: 0.5em 1em; display: inline-block; }
+table { border-collapse: collapse; margin-bottom: 1.5em; width: 100%; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }
+th { background: #f0f0f0; }
+td.size { text-align: right; white-space: nowrap; }
+tr.episode td { background: #fafafa; font-weight: bold; }
+tr.keep td { background: #eef6ee; }
+";
+
+// Everything, including the styling, goes in the one file, so it can be mailed around.
+pub fn write_html(groups: &Vec<Vec<AnimeFile>>, path: &Path) -> io::Result<()> {
+    // Series, then season, then the sets of duplicates in it.
+    let mut by_series: BTreeMap<String, (String, BTreeMap<SeasonNum, Vec<&Vec<AnimeFile>>>)> = BTreeMap::new();
+    for files in groups.iter() {
+        let series = by_series.entry(normalize_title(&files[0].title)).or_insert((files[0].title.clone(), BTreeMap::new()));
+        series.1.entry(files[0].season.clone()).or_insert(Vec::new()).push(files);
+    }
+    let total: u64 = groups.iter().map(|g| reclaimable_bytes(g)).sum();
+    let generated = time::now().strftime("%Y-%m-%d %H:%M:%S").map(|t| t.to_string()).unwrap_or("".to_owned());
+
+    let mut file = try!(File::create(path));
+    try!(writeln!(file, "<!DOCTYPE html>"));
+    try!(writeln!(file, "<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Anime duplicates</title>"));
+    try!(writeln!(file, "<style>{}</style>\n</head>\n<body>", HTML_STYLE));
+    try!(writeln!(file, "<h1>Anime duplicates</h1>"));
+    try!(writeln!(file, "<p class=\"generated\">Generated {}</p>", html_escape(&generated)));
+    try!(writeln!(file, "<p class=\"total\">{} sets of duplicates. Space that could be reclaimed: <strong>{}</strong></p>",
+                  groups.len(), html_escape(&format_bytes(total))));
+
+    for (_, &(ref title, ref seasons)) in by_series.iter() {
+        let series_total: u64 = seasons.values().flat_map(|g| g.iter()).map(|g| reclaimable_bytes(g)).sum();
+        try!(writeln!(file, "<h2>{} <small>({} reclaimable)</small></h2>", html_escape(title), html_escape(&format_bytes(series_total))));
+
+        for (season, season_groups) in seasons.iter() {
+            try!(writeln!(file, "<h3>{}</h3>", html_escape(&season_label(season))));
+            try!(writeln!(file, "<table>\n<tr><th>File</th><th>Size</th><th>Media</th><th>Resolution</th><th>Group</th></tr>"));
+            for files in season_groups.iter() {
+                let keeper = ranking::recommended_keeper(files);
+                try!(writeln!(file, "<tr class=\"episode\"><td colspan=\"5\">{:?} ({} reclaimable)</td></tr>",
+                              files[0].episode, html_escape(&format_bytes(reclaimable_bytes(files)))));
+                for (i, f) in files.iter().enumerate() {
+                    let name = match f.file_name.file_name() {
+                        Some(n) => n.to_string_lossy().into_owned(),
+                        None    => f.file_name.to_string_lossy().into_owned(),
+                    };
+                    try!(writeln!(file, "<tr{}><td title=\"{}\">{}{}</td><td class=\"size\">{}</td><td>{:?}</td><td>{}</td><td>{}</td></tr>",
+                                  if keeper == Some(i) { " class=\"keep\"" } else { "" },
+                                  html_escape(&f.file_name.to_string_lossy()),
+                                  html_escape(&name),
+                                  if keeper == Some(i) { " (keep)" } else { "" },
+                                  html_escape(&format_bytes(f.size)),
+                                  f.source_media,
+                                  html_escape(&resolution(f)),
+                                  html_escape(&f.release_group.clone().unwrap_or("".to_owned()))));
+                }
+            }
+            try!(writeln!(file, "</table>"));
+        }
+    }
+
+    try!(writeln!(file, "</body>\n</html>"));
+    Ok(())
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+        .replace("'", "&#39;")
+}
+
+#[test]
+fn csv_field_quotes_when_needed() {
+    assert_eq!("Fairy Tail",                  csv_field("Fairy Tail"));
+    assert_eq!("\"Working!!, Season 2\"",     csv_field("Working!!, Season 2"));
+    assert_eq!("\"The \"\"Garden\"\" of Sinners\"", csv_field("The \"Garden\" of Sinners"));
+}
+
+#[test]
+fn reclaimable_bytes_keeps_recommended_file() {
+    let mut files = anime_files(vec!(
+        "./Fairy Tail - S01E01 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4",
+        "./Fairy Tail - S01E01 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls](8e28f917be6423ce5ee4deee1369eb4e2eb02e48).mkv",
+    ));
+    files[0].size = 300;
+    files[1].size = 1000;
+
+    assert_eq!(300, reclaimable_bytes(&files));
+    assert_eq!("1000 bytes", format_bytes(1000));
+    assert_eq!("1.5 GiB",    format_bytes(1610612736));
+}
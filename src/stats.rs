@@ -0,0 +1,125 @@
+use ansi_term::Colour::Yellow;
+
+use std::collections::BTreeMap;
+use std::io;
+
+// Traits
+use std::io::Write;
+
+use normalize_title;
+use report::{assumed_keeper, format_bytes};
+#[cfg(test)] use anime_files;
+use AnimeFile;
+use ScanResult;
+
+// Bytes in sets of duplicates, and how many of those could be freed up.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SpaceUsage {
+    pub duplicate_bytes:   u64,
+    pub reclaimable_bytes: u64,
+}
+
+// Running totals for the end of run summary.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Stats {
+    pub dirs_scanned:  usize,
+    pub files_scanned: usize,
+    pub unparsed:      usize,
+    // Files that were neither videos nor companions, by extension.
+    pub skipped:       BTreeMap<String, usize>,
+    pub groups:        usize,
+    pub total:         SpaceUsage,
+    pub by_media:      BTreeMap<String, SpaceUsage>,
+    // Keyed by the normalized title, with the title as it was first seen.
+    pub by_series:     BTreeMap<String, (String, SpaceUsage)>,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    pub fn add_scan(&mut self, scan: &ScanResult) {
+        self.dirs_scanned  += 1;
+        self.files_scanned += scan.files_seen;
+        self.unparsed      += scan.unparsed;
+        for (extension, count) in scan.skipped.iter() {
+            *self.skipped.entry(extension.clone()).or_insert(0) += *count;
+        }
+    }
+
+    // Count a set of duplicate episodes, as if everything but the recommended keeper were removed.
+    pub fn add_group(&mut self, files: &Vec<AnimeFile>) {
+        self.groups += 1;
+
+        let keeper = assumed_keeper(files);
+        for (i, file) in files.iter().enumerate() {
+            let reclaimable = if i == keeper { 0 } else { file.size };
+
+            let media = self.by_media.entry(format!("{:?}", file.source_media)).or_insert(SpaceUsage::default());
+            media.duplicate_bytes   += file.size;
+            media.reclaimable_bytes += reclaimable;
+
+            let series = self.by_series.entry(normalize_title(&file.title)).or_insert((file.title.clone(), SpaceUsage::default()));
+            series.1.duplicate_bytes   += file.size;
+            series.1.reclaimable_bytes += reclaimable;
+
+            self.total.duplicate_bytes   += file.size;
+            self.total.reclaimable_bytes += reclaimable;
+        }
+    }
+
+    pub fn print<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        try!(writeln!(out, "{}", Yellow.paint("Summary:")));
+        try!(writeln!(out, "  Directories scanned:       {}", self.dirs_scanned));
+        try!(writeln!(out, "  Files scanned:             {}", self.files_scanned));
+        try!(writeln!(out, "  Files that didn't parse:   {}", self.unparsed));
+        try!(writeln!(out, "  Files skipped:             {}", self.skipped.values().sum::<usize>()));
+        for (extension, count) in self.skipped.iter() {
+            let extension = if extension.len() == 0 { "(no extension)".to_owned() } else { format!(".{}", extension) };
+            try!(writeln!(out, "    {}: {}", extension, count));
+        }
+        try!(writeln!(out, "  Sets of duplicates:        {}", self.groups));
+        try!(writeln!(out, "  Bytes in duplicates:       {}", format_bytes(self.total.duplicate_bytes)));
+        try!(writeln!(out, "  Reclaimable:               {}", format_bytes(self.total.reclaimable_bytes)));
+
+        if self.groups == 0 {
+            return Ok(());
+        }
+        try!(writeln!(out, "  {}", Yellow.paint("By source media:")));
+        for (media, usage) in self.by_media.iter() {
+            try!(print_usage(out, media, usage));
+        }
+        try!(writeln!(out, "  {}", Yellow.paint("By series:")));
+        for &(ref title, ref usage) in self.by_series.values() {
+            try!(print_usage(out, title, usage));
+        }
+        Ok(())
+    }
+}
+
+fn print_usage<W: Write>(out: &mut W, name: &str, usage: &SpaceUsage) -> io::Result<()> {
+    writeln!(out, "    {}: {} in duplicates, {} reclaimable", name, format_bytes(usage.duplicate_bytes), format_bytes(usage.reclaimable_bytes))
+}
+
+#[test]
+fn add_group_splits_space_by_media_and_series() {
+    let mut files = anime_files(vec!(
+        "./Fairy Tail - S01E01 [HDTV][1280x720.H264AVC.AAC][Kyuubi](304a75ced2d46016e3df0c8b4607f4afe4e75952).mp4",
+        "./Fairy Tail - S01E01 [Blu-ray][1920x1080.H264AVC.FLAC][Coalgirls](8e28f917be6423ce5ee4deee1369eb4e2eb02e48).mkv",
+        "./fairy tail - S01E01 [HDTV][1280x720.H264AVC.AAC][HorribleSubs](0123456789abcdef0123456789abcdef01234567).mkv",
+    ));
+    files[0].size = 300;
+    files[1].size = 1000;
+    files[2].size = 200;
+
+    let mut stats = Stats::new();
+    stats.add_group(&files);
+
+    assert_eq!(1, stats.groups);
+    assert_eq!(SpaceUsage { duplicate_bytes: 1500, reclaimable_bytes: 500 }, stats.total);
+    assert_eq!(Some(&SpaceUsage { duplicate_bytes: 500, reclaimable_bytes: 500 }), stats.by_media.get("HDTV"));
+    assert_eq!(Some(&SpaceUsage { duplicate_bytes: 1000, reclaimable_bytes: 0 }), stats.by_media.get("BluRay"));
+    assert_eq!(1, stats.by_series.len());
+    assert_eq!("Fairy Tail", stats.by_series["fairy tail"].0);
+}
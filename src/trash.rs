@@ -0,0 +1,264 @@
+// Move files to the trash, following the freedesktop.org Trash specification:
+// https://specifications.freedesktop.org/trash-spec/trashspec-latest.html
+use libc;
+use time;
+
+use std::env;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Traits
+use std::ffi::OsStr;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+use removal;
+
+const STICKY_BIT: u32 = 0o1000;
+
+// Move the file into the trash for the file system it lives on, returning where it ended up. If that
+// file system has no usable trash (it's read-only at the top, say), the file is copied into the home
+// trash instead, as the spec allows.
+pub fn trash_file(path: &Path) -> io::Result<PathBuf> {
+    let path = try!(absolute_path(path));
+    let file_dev = try!(fs::symlink_metadata(&path)).dev();
+
+    let home_trash = try!(home_trash_dir());
+    if try!(device_of_nearest_existing(&home_trash)) == file_dev {
+        return trash_file_in(&path, &home_trash, None);
+    }
+
+    let topdir = try!(mount_point(&path, file_dev));
+    match topdir_trash_dir(&topdir) {
+        Ok(trash_dir) => trash_file_in(&path, &trash_dir, Some(&topdir)),
+        Err(e)        => {
+            warn!("No usable trash on {} ({}), copying {} to {} instead", topdir.display(), e, path.display(), home_trash.display());
+            trash_file_in(&path, &home_trash, None)
+        },
+    }
+}
+
+// Move the (absolute) path into trash_dir, copying it if trash_dir is on another file system. When
+// the trash directory belongs to a mount point, the path recorded in the .trashinfo file is relative
+// to that mount point.
+fn trash_file_in(path: &Path, trash_dir: &Path, topdir: Option<&Path>) -> io::Result<PathBuf> {
+    let files_dir = trash_dir.join("files");
+    let info_dir  = trash_dir.join("info");
+    try!(create_private_dir(&files_dir));
+    try!(create_private_dir(&info_dir));
+
+    let recorded_path = match topdir {
+        Some(t) => path.strip_prefix(t).unwrap_or(path).to_path_buf(),
+        None    => path.to_path_buf(),
+    };
+    let deletion_date = match time::now().strftime("%Y-%m-%dT%H:%M:%S") {
+        Ok(d)  => d.to_string(),
+        Err(e) => return Err(io::Error::new(io::ErrorKind::Other, format!("Unable to format deletion date: {}", e))),
+    };
+    let trash_info = format!("[Trash Info]\nPath={}\nDeletionDate={}\n", url_escape(&recorded_path), deletion_date);
+
+    let name = match path.file_name() {
+        Some(n) => n.to_os_string(),
+        None    => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("No file name in {}", path.display()))),
+    };
+
+    // Creating the .trashinfo file with create_new is what reserves the name in the trash, so keep
+    // trying new names until one isn't taken.
+    let mut attempt = 1;
+    loop {
+        let mut trash_name = name.clone();
+        if attempt > 1 {
+            trash_name.push(format!(".{}", attempt));
+        }
+        let mut info_name = trash_name.clone();
+        info_name.push(".trashinfo");
+        let info_path = info_dir.join(info_name);
+        let destination = files_dir.join(&trash_name);
+
+        let mut info_file = match OpenOptions::new().write(true).create_new(true).open(&info_path) {
+            Ok(f)  => f,
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                attempt += 1;
+                continue;
+            },
+            Err(e) => return Err(e),
+        };
+
+        // The move never replaces anything, so a file already in files/ without a .trashinfo (left
+        // over from something that didn't clean up properly) just means trying the next name.
+        let result = info_file.write_all(trash_info.as_bytes()).and_then(|_| removal::move_file(path, &destination));
+        return match result {
+            Ok(_)  => Ok(destination),
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                try!(fs::remove_file(&info_path));
+                attempt += 1;
+                continue;
+            },
+            Err(e) => {
+                let _ = fs::remove_file(&info_path);
+                Err(e)
+            },
+        };
+    }
+}
+
+fn absolute_path(path: &Path) -> io::Result<PathBuf> {
+    let parent = match path.parent() {
+        Some(p) if p.as_os_str().len() > 0 => p.to_path_buf(),
+        _                                  => PathBuf::from("."),
+    };
+    let name = match path.file_name() {
+        Some(n) => n,
+        None    => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("No file name in {}", path.display()))),
+    };
+
+    Ok(try!(parent.canonicalize()).join(name))
+}
+
+fn home_trash_dir() -> io::Result<PathBuf> {
+    let data_home = match env::var_os("XDG_DATA_HOME") {
+        Some(ref d) if d.len() > 0 => PathBuf::from(d),
+        _                          => match env::home_dir() {
+            Some(h) => h.join(".local").join("share"),
+            None    => return Err(io::Error::new(io::ErrorKind::NotFound, "Unable to find the home directory")),
+        },
+    };
+
+    Ok(data_home.join("Trash"))
+}
+
+// The home trash might not exist yet, so look at the closest directory that does.
+fn device_of_nearest_existing(path: &Path) -> io::Result<u64> {
+    let mut current = Some(path);
+    while let Some(p) = current {
+        if let Ok(m) = fs::metadata(p) {
+            return Ok(m.dev());
+        }
+        current = p.parent();
+    }
+
+    Err(io::Error::new(io::ErrorKind::NotFound, format!("Unable to find any part of {}", path.display())))
+}
+
+// Walk up from the path until the parent is on a different device.
+fn mount_point(path: &Path, dev: u64) -> io::Result<PathBuf> {
+    let mut topdir = path.to_path_buf();
+    loop {
+        let parent = match topdir.parent() {
+            Some(p) => p.to_path_buf(),
+            None    => return Ok(topdir),
+        };
+        if try!(fs::metadata(&parent)).dev() != dev {
+            return Ok(topdir);
+        }
+        topdir = parent;
+    }
+}
+
+// Use $topdir/.Trash/$uid if the administrator has set up $topdir/.Trash properly (a real directory
+// with the sticky bit set), and $topdir/.Trash-$uid otherwise.
+fn topdir_trash_dir(topdir: &Path) -> io::Result<PathBuf> {
+    let uid = unsafe { libc::getuid() };
+
+    let shared_trash = topdir.join(".Trash");
+    match fs::symlink_metadata(&shared_trash) {
+        Ok(ref m) if m.is_dir() && m.permissions().mode() & STICKY_BIT != 0 => {
+            let user_trash = shared_trash.join(format!("{}", uid));
+            if create_private_dir(&user_trash).is_ok() {
+                return Ok(user_trash);
+            }
+            warn!("Unable to use {}, falling back to {}/.Trash-{}", user_trash.display(), topdir.display(), uid);
+        },
+        Ok(_)  => warn!("Ignoring {}: not a directory with the sticky bit set", shared_trash.display()),
+        Err(_) => { },
+    }
+
+    let user_trash = topdir.join(format!(".Trash-{}", uid));
+    try!(create_private_dir(&user_trash));
+    Ok(user_trash)
+}
+
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)
+}
+
+// Percent-encode the path the way the trash spec wants it: as a URL path, leaving '/' alone.
+fn url_escape(path: &Path) -> String {
+    let bytes: &[u8] = OsStr::as_bytes(path.as_os_str());
+    let mut escaped = String::new();
+    for &b in bytes {
+        match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => escaped.push(b as char),
+            _ => escaped.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    escaped
+}
+
+#[test]
+fn url_escape_encodes_reserved_characters() {
+    let path = Path::new("/anime/Fairy Tail - S01E01 [HDTV](304a).mkv");
+    assert_eq!("/anime/Fairy%20Tail%20-%20S01E01%20%5BHDTV%5D%28304a%29.mkv", url_escape(path));
+}
+
+#[test]
+fn trash_file_in_writes_trashinfo() {
+    use std::fs::File;
+    use std::io::Read;
+
+    let root = env::temp_dir().join("anime-dupe-finder-trash_file_in_writes_trashinfo");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let trash_dir = root.join("Trash");
+    let file = root.join("Texhnolyze - S01ES5.mkv");
+
+    for _ in 0..2 {
+        File::create(&file).unwrap();
+        trash_file_in(&file, &trash_dir, Some(&root)).unwrap();
+    }
+
+    let mut info = String::new();
+    File::open(trash_dir.join("info").join("Texhnolyze - S01ES5.mkv.2.trashinfo")).unwrap().read_to_string(&mut info).unwrap();
+    let file_exists = file.exists();
+    let first_exists = trash_dir.join("files").join("Texhnolyze - S01ES5.mkv").exists();
+    let second_exists = trash_dir.join("files").join("Texhnolyze - S01ES5.mkv.2").exists();
+    fs::remove_dir_all(&root).unwrap();
+
+    assert!(!file_exists);
+    assert!(first_exists);
+    assert!(second_exists);
+    assert!(info.starts_with("[Trash Info]\nPath=Texhnolyze%20-%20S01ES5.mkv\nDeletionDate="));
+}
+
+#[test]
+fn trash_file_in_leaves_files_without_trashinfo_alone() {
+    use std::fs::File;
+    use std::io::Read;
+
+    let root = env::temp_dir().join("anime-dupe-finder-trash_file_in_leaves_files_without_trashinfo_alone");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("Trash").join("files")).unwrap();
+    let trash_dir = root.join("Trash");
+    let file = root.join("Texhnolyze - S01ES5.mkv");
+    File::create(&file).unwrap().write_all(b"new").unwrap();
+    File::create(trash_dir.join("files").join("Texhnolyze - S01ES5.mkv")).unwrap().write_all(b"left over").unwrap();
+
+    let destination = trash_file_in(&file, &trash_dir, Some(&root)).unwrap();
+
+    let mut left_over = String::new();
+    File::open(trash_dir.join("files").join("Texhnolyze - S01ES5.mkv")).unwrap().read_to_string(&mut left_over).unwrap();
+    let first_info_exists = trash_dir.join("info").join("Texhnolyze - S01ES5.mkv.trashinfo").exists();
+    let second_info_exists = trash_dir.join("info").join("Texhnolyze - S01ES5.mkv.2.trashinfo").exists();
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(trash_dir.join("files").join("Texhnolyze - S01ES5.mkv.2"), destination);
+    assert_eq!("left over", left_over);
+    assert!(!first_info_exists);
+    assert!(second_info_exists);
+}
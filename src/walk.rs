@@ -0,0 +1,350 @@
+// Listing directories for the scanner: what to do about symlinks, hidden files, and paths the user
+// wants left out, and keeping track of which directories have already been scanned.
+use glob::Pattern;
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Traits
+use std::os::unix::fs::MetadataExt;
+
+use error::Error;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SymlinkPolicy {
+    // Treat symlinks like whatever they point to. A symlink to a parent directory will loop.
+    Follow,
+    // Ignore symlinks, whether they point to files or directories.
+    Skip,
+    // Follow symlinks, but never scan the same directory (by device and inode) twice. Symlinks to
+    // directories under one of the directories searched are skipped, in favor of the real path.
+    Detect,
+}
+
+impl SymlinkPolicy {
+    pub fn names() -> &'static [&'static str] {
+        &["follow", "skip", "detect"]
+    }
+
+    pub fn from_name(name: &str) -> Result<SymlinkPolicy, String> {
+        match name {
+            "follow" => Ok(SymlinkPolicy::Follow),
+            "skip"   => Ok(SymlinkPolicy::Skip),
+            "detect" => Ok(SymlinkPolicy::Detect),
+            _        => Err(format!("Unknown symlink policy: {}", name)),
+        }
+    }
+}
+
+// Extensions of files that might be episodes, unless told otherwise.
+pub const DEFAULT_VIDEO_EXTENSIONS: &'static [&'static str] = &[
+    "mkv", "mp4", "avi", "ogm", "wmv", "m4v", "mov", "mpg", "mpeg", "ts", "m2ts", "webm", "flv", "rmvb",
+];
+
+// Extensions of files that go along with an episode: subtitles and external audio tracks.
+pub const DEFAULT_COMPANION_EXTENSIONS: &'static [&'static str] = &[
+    "srt", "ass", "ssa", "ac3", "idx", "sub", "dts", "flac", "mka",
+];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FileKind {
+    Video,
+    Companion,
+    // Anything else: .nfo files, cover art, and so on.
+    Other,
+}
+
+// A file's extension, lowercased, for matching against the extension lists.
+pub fn extension(path: &Path) -> Option<String> {
+    path.extension().map(|e| e.to_string_lossy().to_lowercase())
+}
+
+// A glob pattern for --include or --exclude. Patterns with a '/' in them are matched against the
+// whole path, and the rest against just the file or directory name, so "@eaDir" matches anywhere.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PathPattern {
+    pattern:    Pattern,
+    whole_path: bool,
+}
+
+impl PathPattern {
+    pub fn new(pattern: &str) -> Result<PathPattern, String> {
+        match Pattern::new(pattern) {
+            Ok(p)  => Ok(PathPattern { pattern: p, whole_path: pattern.contains('/') }),
+            Err(e) => Err(format!("Invalid pattern \"{}\": {}", pattern, e.msg)),
+        }
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        if self.whole_path {
+            self.pattern.matches(&path.to_string_lossy())
+        } else {
+            match path.file_name() {
+                Some(n) => self.pattern.matches(&n.to_string_lossy()),
+                None    => false,
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct WalkOptions {
+    pub symlinks: SymlinkPolicy,
+    // Whether to look at files and directories whose names start with '.'.
+    pub hidden:   bool,
+    // How many directories to scan at once.
+    pub jobs:     usize,
+    // Files and directories matching any of these are left out.
+    pub exclude:  Vec<PathPattern>,
+    // If there are any, only files matching one of them are looked at. Directories are still
+    // searched.
+    pub include:  Vec<PathPattern>,
+    // How many levels of directories below the ones given to search. None for no limit.
+    pub max_depth:       Option<usize>,
+    // Don't search directories on a different file system from the one they were found in.
+    pub one_file_system: bool,
+    // Lowercase, without the '.'.
+    pub video_extensions:     Vec<String>,
+    pub companion_extensions: Vec<String>,
+}
+
+impl WalkOptions {
+    pub fn new() -> WalkOptions {
+        WalkOptions {
+            symlinks:        SymlinkPolicy::Detect,
+            hidden:          false,
+            jobs:            1,
+            exclude:         Vec::new(),
+            include:         Vec::new(),
+            max_depth:       None,
+            one_file_system: false,
+            video_extensions:     DEFAULT_VIDEO_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+            companion_extensions: DEFAULT_COMPANION_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    pub fn file_kind(&self, path: &Path) -> FileKind {
+        match extension(path) {
+            Some(ref e) if self.video_extensions.contains(e)     => FileKind::Video,
+            Some(ref e) if self.companion_extensions.contains(e) => FileKind::Companion,
+            _                                                    => FileKind::Other,
+        }
+    }
+}
+
+// The contents of a single directory, sorted.
+pub struct Listing {
+    pub dirs:   Vec<PathBuf>,
+    pub files:  Vec<PathBuf>,
+    pub errors: Vec<Error>,
+}
+
+pub fn list_dir(dir: &Path, options: &WalkOptions) -> Listing {
+    let mut listing = Listing { dirs: Vec::new(), files: Vec::new(), errors: Vec::new() };
+
+    let entries = match fs::read_dir(dir) {
+        Ok(e)  => e,
+        Err(e) => {
+            listing.errors.push(Error::Unreadable(dir.to_path_buf(), e));
+            return listing;
+        },
+    };
+    let dir_device = if options.one_file_system {
+        match fs::metadata(dir) {
+            Ok(m)  => Some(m.dev()),
+            Err(e) => {
+                listing.errors.push(Error::Unreadable(dir.to_path_buf(), e));
+                return listing;
+            },
+        }
+    } else {
+        None
+    };
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(e)  => e,
+            Err(e) => {
+                listing.errors.push(Error::Unreadable(dir.to_path_buf(), e));
+                continue;
+            },
+        };
+        let path = entry.path();
+
+        if !options.hidden && is_hidden(&path) {
+            debug!("Skipping hidden: {}", path.display());
+            continue;
+        }
+        if options.exclude.iter().any(|p| p.matches(&path)) {
+            debug!("Skipping excluded: {}", path.display());
+            continue;
+        }
+
+        let file_type = match entry.file_type() {
+            Ok(t)  => t,
+            Err(e) => {
+                listing.errors.push(Error::Unreadable(path, e));
+                continue;
+            },
+        };
+        let is_dir = if file_type.is_symlink() {
+            if options.symlinks == SymlinkPolicy::Skip {
+                debug!("Skipping symlink: {}", path.display());
+                continue;
+            }
+            // Where the symlink points decides what it is. Broken symlinks are neither.
+            match fs::metadata(&path) {
+                Ok(m) if m.is_dir()  => true,
+                Ok(m) if m.is_file() => false,
+                _                    => {
+                    debug!("Skipping symlink to nothing useful: {}", path.display());
+                    continue;
+                },
+            }
+        } else if file_type.is_dir() {
+            true
+        } else if file_type.is_file() {
+            false
+        } else {
+            continue;
+        };
+
+        if is_dir {
+            if let Some(dev) = dir_device {
+                match fs::metadata(&path) {
+                    Ok(ref m) if m.dev() != dev => {
+                        debug!("Skipping directory on another file system: {}", path.display());
+                        continue;
+                    },
+                    _                           => { },
+                }
+            }
+            listing.dirs.push(path);
+        } else if options.include.len() == 0 || options.include.iter().any(|p| p.matches(&path)) {
+            listing.files.push(path);
+        } else {
+            debug!("Skipping file not included: {}", path.display());
+        }
+    }
+
+    listing.dirs.sort();
+    listing.files.sort();
+    listing
+}
+
+fn is_hidden(path: &Path) -> bool {
+    match path.file_name() {
+        Some(n) => n.to_string_lossy().starts_with("."),
+        None    => false,
+    }
+}
+
+// Whether the path is a symlink to a directory under one of the roots, given as canonical paths.
+// Those are scanned under their real paths, whichever order they're found in.
+pub fn is_link_into(path: &Path, real_roots: &Vec<PathBuf>) -> bool {
+    match fs::symlink_metadata(path) {
+        Ok(ref m) if m.file_type().is_symlink() => { },
+        _                                       => return false,
+    }
+    match fs::canonicalize(path) {
+        Ok(target) => real_roots.iter().any(|r| target.starts_with(r)),
+        Err(_)     => false,
+    }
+}
+
+// Directories already scanned, by device and inode, so that symlinks (or bind mounts) back up the
+// tree don't send the scan around in circles.
+pub struct Visited {
+    seen: HashSet<(u64, u64)>,
+}
+
+impl Visited {
+    pub fn new() -> Visited {
+        Visited { seen: HashSet::new() }
+    }
+
+    // False if the directory has been seen before. Directories that can't be looked at count as
+    // new, so that whatever is wrong with them gets reported when they're listed.
+    pub fn first_visit(&mut self, dir: &Path) -> bool {
+        match fs::metadata(dir) {
+            Ok(m)  => self.seen.insert((m.dev(), m.ino())),
+            Err(_) => true,
+        }
+    }
+}
+
+#[test]
+fn list_dir_applies_symlink_and_hidden_options() {
+    use std::env;
+    use std::fs::File;
+    use std::os::unix;
+
+    let root = env::temp_dir().join("anime-dupe-finder-list_dir_applies_symlink_and_hidden_options");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("Show")).unwrap();
+    File::create(root.join("Show").join("Show - S01E01.mkv")).unwrap();
+    File::create(root.join(".Show - S01E01.mkv.part")).unwrap();
+    unix::fs::symlink(root.join("Show"), root.join("Link")).unwrap();
+
+    let default = list_dir(&root, &WalkOptions::new());
+    let mut skipping_options = WalkOptions::new();
+    skipping_options.symlinks = SymlinkPolicy::Skip;
+    skipping_options.hidden = true;
+    let skipping = list_dir(&root, &skipping_options);
+    let mut visited = Visited::new();
+    let first = visited.first_visit(&root.join("Show"));
+    let through_link = visited.first_visit(&root.join("Link"));
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(vec!(root.join("Link"), root.join("Show")), default.dirs);
+    assert_eq!(0, default.files.len());
+    assert_eq!(vec!(root.join("Show")), skipping.dirs);
+    assert_eq!(vec!(root.join(".Show - S01E01.mkv.part")), skipping.files);
+    assert!(first);
+    assert!(!through_link);
+}
+
+#[test]
+fn list_dir_keeps_directories_on_the_same_file_system() {
+    use std::env;
+    use std::fs::File;
+
+    let root = env::temp_dir().join("anime-dupe-finder-list_dir_keeps_directories_on_the_same_file_system");
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("Show")).unwrap();
+    File::create(root.join("Show - S01E01.mkv")).unwrap();
+
+    let mut options = WalkOptions::new();
+    options.one_file_system = true;
+    let listing = list_dir(&root, &options);
+    fs::remove_dir_all(&root).unwrap();
+
+    assert_eq!(vec!(root.join("Show")), listing.dirs);
+    assert_eq!(vec!(root.join("Show - S01E01.mkv")), listing.files);
+}
+
+#[test]
+fn path_pattern_matches_names_or_whole_paths() {
+    let by_name = PathPattern::new("@eaDir").unwrap();
+    let by_path = PathPattern::new("*/_archive/*").unwrap();
+
+    assert!(by_name.matches(Path::new("/srv/anime/Fairy Tail/@eaDir")));
+    assert!(!by_name.matches(Path::new("/srv/anime/@eaDir/Fairy Tail")));
+    assert!(by_path.matches(Path::new("/srv/anime/_archive/Fairy Tail")));
+    assert!(!by_path.matches(Path::new("/srv/anime/Fairy Tail/_archive")));
+    assert!(PathPattern::new("[").is_err());
+}
+
+#[test]
+fn file_kind_uses_extension_lists() {
+    let mut options = WalkOptions::new();
+
+    assert_eq!(FileKind::Video,     options.file_kind(Path::new("Fairy Tail - S01E01 [HDTV].MKV")));
+    assert_eq!(FileKind::Companion, options.file_kind(Path::new("Fairy Tail - S01E01 [HDTV].ass")));
+    assert_eq!(FileKind::Other,     options.file_kind(Path::new("Fairy Tail - S01E01 [HDTV].nfo")));
+    assert_eq!(FileKind::Other,     options.file_kind(Path::new("Fairy Tail")));
+
+    options.video_extensions = vec!("ogm".to_owned());
+    assert_eq!(FileKind::Other,     options.file_kind(Path::new("Fairy Tail - S01E01 [HDTV].mkv")));
+}